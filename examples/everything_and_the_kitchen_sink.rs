@@ -1,7 +1,7 @@
 extern crate pishock_rs;
 
-use log::{error, info, log};
-use pishock_rs::interpolation::ShockPoint;
+use log::error;
+use pishock_rs::interpolation::{CurveOptions, Easing, ShockPoint};
 use pishock_rs::PiShocker;
 use simplelog::{Config, LevelFilter, TerminalMode};
 use std::process::exit;
@@ -19,9 +19,9 @@ async fn main() {
 
     println!("Simple example of using the PiShock API - env variable control");
 
-    let shocker_share_code = std::env::var("PISHOCK_SHARECODE").unwrap_or(String::new());
-    let shocker_api_key = std::env::var("PISHOCK_APIKEY").unwrap_or(String::new());
-    let shocker_api_username = std::env::var("PISHOCK_USERNAME").unwrap_or(String::new());
+    let shocker_share_code = std::env::var("PISHOCK_SHARECODE").unwrap_or_default();
+    let shocker_api_key = std::env::var("PISHOCK_APIKEY").unwrap_or_default();
+    let shocker_api_username = std::env::var("PISHOCK_USERNAME").unwrap_or_default();
 
     println!("Shocker share code (PISHOCK_SHARECODE): {shocker_share_code}");
     println!("Shocker API key (PISHOCK_APIKEY): {shocker_api_key}");
@@ -47,13 +47,16 @@ async fn main() {
         .await
         .unwrap();
     test_pishocker_instance
-        .shock_curve(vec![
-            ShockPoint::new(Duration::from_secs(2), 100),
-            ShockPoint::new(Duration::from_secs(3), 30),
-            ShockPoint::new(Duration::from_secs(1), 1),
-            ShockPoint::new(Duration::from_secs(3), 90),
-            ShockPoint::new(Duration::from_secs(4), 1),
-        ])
+        .shock_curve(
+            vec![
+                ShockPoint::new(Duration::from_secs(2), 100),
+                ShockPoint::new(Duration::from_secs(3), 30),
+                ShockPoint::new(Duration::from_secs(1), 1),
+                ShockPoint::new(Duration::from_secs(3), 90).with_easing(Easing::EaseIn),
+                ShockPoint::new(Duration::from_secs(4), 1),
+            ],
+            CurveOptions::default(),
+        )
         .await
         .unwrap();
 
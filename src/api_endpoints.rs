@@ -1,14 +1,17 @@
 use crate::errors::error_to_pishock_error;
 use crate::pishocker::PiShockerMetadata;
+use crate::validation::Command;
 use crate::{errors, PiShocker};
 use log::debug;
+use reqwest::header::RETRY_AFTER;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time::Instant;
 
-#[derive(Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
-pub(crate) enum PiShockOpCode {
+/// The operations a [`PiShocker`] can perform
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum PiShockOpCode {
     Shock = 0,
     Vibrate = 1,
     Beep = 2,
@@ -20,6 +23,57 @@ impl PiShocker {
         op_code: PiShockOpCode,
         intensity: u32,
         duration: Duration,
+    ) -> Result<(), errors::PiShockError> {
+        // Report the first problem found, in the same order the checks have always run
+        if let Some(violation) = self
+            .validate(&Command::new(op_code, intensity, duration))
+            .into_iter()
+            .next()
+        {
+            return Err(self.violation_to_error(violation));
+        }
+
+        // Bound the rise from the previous shock, which may lower the intensity
+        let reservation = self.limit_escalation(op_code, intensity, duration)?;
+        let intensity = reservation.get_intensity();
+
+        // Charge the exposure budgets, which may turn a shock into a vibration
+        let charge = match self.charge_exposure(op_code, intensity, duration).await {
+            Ok(charge) => charge,
+            Err(error) => {
+                self.release_escalation(&reservation);
+                return Err(error);
+            }
+        };
+        let op_code = charge.get_op();
+
+        // Only shocks raise the baseline of the escalation limiter
+        if op_code != PiShockOpCode::Shock {
+            self.release_escalation(&reservation);
+        }
+
+        // Check shocker cooldown again and record this shock, this has to happen atomically
+        let sent = match self.verify_shocker_cooldown() {
+            Ok(()) => self.send_api_request(op_code, intensity, duration).await,
+            Err(error) => Err(error),
+        };
+
+        // Commands that weren't sent don't use up any budget or raise the baseline
+        if let Err(error) = sent {
+            self.refund_exposure(&charge).await;
+            self.release_escalation(&reservation);
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a command without any of the checks, only for commands that have to get through
+    pub(crate) async fn send_api_request(
+        &self,
+        op_code: PiShockOpCode,
+        intensity: u32,
+        duration: Duration,
     ) -> Result<(), errors::PiShockError> {
         #[derive(Serialize, Deserialize)]
         struct PiShockAPIRequest {
@@ -39,52 +93,6 @@ impl PiShocker {
             username: String,
         }
 
-        if self.get_shocker_online().is_some() && !self.get_shocker_online().unwrap() {
-            return Err(errors::PiShockError::ShockerOffline);
-        }
-
-        if self.get_shocker_paused().is_some() && self.get_shocker_paused().unwrap() {
-            return Err(errors::PiShockError::ShockerPaused);
-        }
-
-        // Check if the intensity is higher than the maximum intensity (in case we have metadata)
-        if self.max_duration_error_triggered(duration).is_some() {
-            return Err(errors::PiShockError::InvalidDuration(
-                self.get_max_duration().unwrap().as_secs() as u32,
-            ));
-        }
-
-        // Check if the duration is higher than the maximum duration (in case we have metadata)
-        if self.max_intensity_error_triggered(intensity).is_some() {
-            return Err(errors::PiShockError::InvalidIntensity(
-                self.get_max_intensity().unwrap() as u32,
-            ));
-        }
-
-        // Check shocker cooldown and return error if it is not over yet
-        self.verify_shocker_cooldown()?;
-
-        // Check for too low intensity (the API does not accept intensities below 1)
-        if intensity < 1 {
-            return Err(errors::PiShockError::InvalidIntensity({
-                if let Some(max_intensity) = self.get_max_intensity() {
-                    max_intensity as u32
-                } else {
-                    100
-                }
-            }));
-        }
-
-        if duration.as_millis() < 100 {
-            return Err(errors::PiShockError::InvalidDuration({
-                if let Some(max_duration) = self.get_max_duration() {
-                    max_duration.as_secs() as u32
-                } else {
-                    15
-                }
-            }));
-        }
-
         let api_duration_number: u32 = self.duration_to_pishock_api(duration);
 
         debug!("Sending request to PiShock API: {{ Op: {}, Intensity: {}, Duration: {}, Code: {}, Apikey: {} }}", op_code as u32, intensity, api_duration_number, self.share_code, self.api_key);
@@ -117,11 +125,10 @@ impl PiShocker {
         } else {
             let response_code = http_response.unwrap_err().status();
 
-            if response_code.is_some() {
+            if let Some(response_code) = response_code {
                 return Err(errors::PiShockError::ConnectionError(format!(
                     "Failed to connect to {}, response code: {}",
-                    self.api_server_url,
-                    response_code.unwrap()
+                    self.api_server_url, response_code
                 )));
             }
 
@@ -166,7 +173,7 @@ impl PiShocker {
             debug!("Response from PiShock API: {}", response.status());
 
             if response.status() != StatusCode::OK {
-                return Err(errors::PiShockError::ShareCodeNotFound);
+                return Err(status_to_pishock_error(&response));
             }
 
             let response_metadata = response.json::<PiShockerMetadata>().await;
@@ -234,6 +241,29 @@ impl PiShocker {
     }
 }
 
+/// Maps a non-200 metadata response to the respective `PiShock` error
+fn status_to_pishock_error(response: &reqwest::Response) -> errors::PiShockError {
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            errors::PiShockError::InvalidCredentials
+        }
+        StatusCode::NOT_FOUND => errors::PiShockError::ShareCodeNotFound,
+        StatusCode::TOO_MANY_REQUESTS => {
+            // Retry-After may also be an HTTP date, which we don't bother parsing
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+
+            errors::PiShockError::RateLimited(retry_after)
+        }
+        status if status.is_server_error() => errors::PiShockError::ServerError(status.as_u16()),
+        status => errors::PiShockError::UnknownError(format!("Unexpected response code: {status}")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::api_endpoints::PiShockOpCode;
@@ -245,7 +275,7 @@ mod tests {
     use std::time::Duration;
     use test_log::test;
 
-    fn successful_server_opcode_mock(opcode: PiShockOpCode, mock_server: &MockServer) -> Mock {
+    fn successful_server_opcode_mock(opcode: PiShockOpCode, mock_server: &MockServer) -> Mock<'_> {
         mock_server.mock(|when, then| {
             when.method(POST)
                 .path("/apioperate/")
@@ -263,7 +293,7 @@ mod tests {
         })
     }
 
-    fn metadata_mock(mock_server: &MockServer) -> Mock {
+    fn metadata_mock(mock_server: &MockServer) -> Mock<'_> {
         mock_server.mock(|when, then| {
             when.method(POST)
                 .path("/GetShockerInfo")
@@ -277,7 +307,7 @@ mod tests {
         })
     }
 
-    fn metadata_mock_unknown(mock_server: &MockServer) -> Mock {
+    fn metadata_mock_unknown(mock_server: &MockServer) -> Mock<'_> {
         mock_server.mock(|when, then| {
             when.method(POST)
                 .path("/GetShockerInfo")
@@ -291,6 +321,20 @@ mod tests {
         })
     }
 
+    fn metadata_mock_status(mock_server: &MockServer, status: u16) -> Mock<'_> {
+        mock_server.mock(|when, then| {
+            when.method(POST)
+                .path("/GetShockerInfo")
+                .header("Content-Type", "application/json")
+                .json_body(json!({
+                    "Code": "sharecode",
+                    "Apikey": "apikey",
+                    "Username": "username"
+                }));
+            then.status(status).header("Retry-After", "30");
+        })
+    }
+
     #[test(tokio::test)]
     async fn metadata_parsing_test() {
         let mockserver = MockServer::start();
@@ -354,6 +398,41 @@ mod tests {
         mock.assert();
     }
 
+    macro_rules! failed_metadata_tests {
+        ($($name:ident: $status:expr => $expected:pat $(if $guard:expr)?,)*) => {
+            $(
+                #[test(tokio::test)]
+                async fn $name() {
+                    let mockserver = MockServer::start();
+
+                    let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+
+                    // Get a PiShocker instance without verification (we can't set the API server URL to the mock server URL yet)
+                    let mut pishocker_instance = pishock_account.get_shocker_without_verification("sharecode".to_string()).await.unwrap();
+
+                    let mock = metadata_mock_status(&mockserver, $status);
+
+                    // Set the API server URL to the mock server URL
+                    pishocker_instance.set_api_server_url(mockserver.url(""));
+
+                    match pishocker_instance.refresh_metadata().await {
+                        Ok(_) => panic!("Expected error, got success"),
+                        Err(e) => assert!(matches!(e, $expected $(if $guard)?), "Unexpected error: {e:?}"),
+                    }
+
+                    mock.assert();
+                }
+            )*
+        }
+    }
+
+    failed_metadata_tests! {
+        metadata_failed_request_unauthorized: 401 => PiShockError::InvalidCredentials,
+        metadata_failed_request_forbidden: 403 => PiShockError::InvalidCredentials,
+        metadata_failed_request_rate_limited: 429 => PiShockError::RateLimited(Some(d)) if d == Duration::from_secs(30),
+        metadata_failed_request_server_error: 503 => PiShockError::ServerError(503),
+    }
+
     macro_rules! successful_opcode_tests {
         ($($name:ident: $value:expr,)*) => {
             $(
@@ -0,0 +1,436 @@
+use crate::errors::PiShockError;
+use crate::interpolation::CurveOptions;
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::validation::MIN_DURATION;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+/// How the loudness of a window of samples is measured
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum EnvelopeMode {
+    /// Root mean square, follows the perceived loudness
+    #[default]
+    Rms,
+    /// The loudest sample, reacts strongly to short transients like drum hits
+    Peak,
+}
+
+/// Settings for turning audio into a [`Pattern`] with [`pattern_from_wav`].
+///
+/// Levels are relative to the loudest window of the file, so quiet recordings still use the whole
+/// intensity range. A level is mapped to `floor + (ceiling - floor) * level ^ gamma`, windows below
+/// the threshold become pauses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioOptions {
+    op: PatternOp,
+    mode: EnvelopeMode,
+    floor: u32,
+    ceiling: u32,
+    gamma: f32,
+    threshold: f32,
+    curve_options: CurveOptions,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self {
+            op: PatternOp::Vibrate,
+            mode: EnvelopeMode::Rms,
+            floor: 1,
+            ceiling: 100,
+            gamma: 1.0,
+            threshold: 0.05,
+            curve_options: CurveOptions::default(),
+        }
+    }
+}
+
+impl AudioOptions {
+    /// Sets the operation of the generated steps (default [`PatternOp::Vibrate`])
+    #[must_use]
+    pub fn with_op(mut self, op: PatternOp) -> Self {
+        self.op = op;
+        self
+    }
+
+    #[must_use]
+    pub fn with_mode(mut self, mode: EnvelopeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the intensity of the quietest window above the threshold (default 1)
+    #[must_use]
+    pub fn with_floor(mut self, floor: u32) -> Self {
+        self.floor = floor;
+        self
+    }
+
+    /// Sets the intensity of the loudest window (default 100)
+    #[must_use]
+    pub fn with_ceiling(mut self, ceiling: u32) -> Self {
+        self.ceiling = ceiling;
+        self
+    }
+
+    /// Sets the curve applied to the levels, values above 1 emphasize loud parts (default 1.0)
+    #[must_use]
+    pub fn with_gamma(mut self, gamma: f32) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Sets the level from 0.0 to 1.0 below which a window becomes a pause (default 0.05)
+    #[must_use]
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the options the pattern will be played with (default [`CurveOptions::default`]).
+    ///
+    /// The window length is the resolution, and every step is shortened by the step gap so the
+    /// pattern stays in sync with the audio when played with the same options.
+    #[must_use]
+    pub fn with_curve_options(mut self, curve_options: CurveOptions) -> Self {
+        self.curve_options = curve_options;
+        self
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> PatternOp {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_mode(&self) -> EnvelopeMode {
+        self.mode
+    }
+
+    #[must_use]
+    pub fn get_floor(&self) -> u32 {
+        self.floor
+    }
+
+    #[must_use]
+    pub fn get_ceiling(&self) -> u32 {
+        self.ceiling
+    }
+
+    #[must_use]
+    pub fn get_gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    #[must_use]
+    pub fn get_threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    #[must_use]
+    pub fn get_curve_options(&self) -> CurveOptions {
+        self.curve_options
+    }
+
+    fn verify(&self) -> Result<(), PiShockError> {
+        self.curve_options.verify()?;
+
+        if self.floor < 1 || self.floor > self.ceiling || self.ceiling > 100 {
+            return Err(PiShockError::InvalidCurveOptions(format!(
+                "floor and ceiling must satisfy 1 <= floor <= ceiling <= 100, got {} and {}",
+                self.floor, self.ceiling
+            )));
+        }
+
+        if !(self.gamma > 0.0 && self.gamma.is_finite()) {
+            return Err(PiShockError::InvalidCurveOptions(format!(
+                "gamma must be positive, got {}",
+                self.gamma
+            )));
+        }
+
+        if !(0.0..=1.0).contains(&self.threshold) {
+            return Err(PiShockError::InvalidCurveOptions(format!(
+                "threshold must be between 0.0 and 1.0, got {}",
+                self.threshold
+            )));
+        }
+
+        let resolution = self.curve_options.get_resolution();
+        if resolution.saturating_sub(self.curve_options.get_step_gap()) < MIN_DURATION {
+            return Err(PiShockError::InvalidCurveOptions(format!(
+                "resolution {resolution:?} leaves less than {MIN_DURATION:?} per step after the step gap"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Maps a level relative to the loudest window to an intensity, `None` for a pause
+    fn intensity(&self, level: f32) -> Option<u32> {
+        if level < self.threshold || level <= 0.0 {
+            return None;
+        }
+
+        let range = (self.ceiling - self.floor) as f32;
+        Some(self.floor + (range * level.powf(self.gamma)).round() as u32)
+    }
+}
+
+/// Reads a WAV file and turns its loudness envelope into a [`Pattern`].
+///
+/// ```no_run
+/// # tokio_test::block_on(async {
+/// use pishock_rs::audio::{pattern_from_wav, AudioOptions};
+/// use pishock_rs::interpolation::CurveOptions;
+/// use pishock_rs::PiShockAccount;
+///
+/// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+/// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+///
+/// let pattern = pattern_from_wav("song.wav", AudioOptions::default().with_ceiling(60)).unwrap();
+/// pishocker_instance.play_pattern(&pattern, CurveOptions::default()).await.unwrap();
+/// # })
+/// ```
+/// # Errors
+/// Returns [`PiShockError::InvalidAudio`] if the file can't be read or decoded and
+/// [`PiShockError::InvalidCurveOptions`] if the options are invalid.
+pub fn pattern_from_wav<P: AsRef<Path>>(
+    path: P,
+    options: AudioOptions,
+) -> Result<Pattern, PiShockError> {
+    let reader = hound::WavReader::open(path)
+        .map_err(|error| PiShockError::InvalidAudio(error.to_string()))?;
+
+    pattern_from_wav_reader(reader, options)
+}
+
+/// Like [`pattern_from_wav`], but reads the WAV data from any reader, e.g. an embedded file
+///
+/// # Errors
+/// Returns [`PiShockError::InvalidAudio`] if the data can't be decoded and
+/// [`PiShockError::InvalidCurveOptions`] if the options are invalid.
+pub fn pattern_from_wav_data<R: Read>(
+    data: R,
+    options: AudioOptions,
+) -> Result<Pattern, PiShockError> {
+    let reader = hound::WavReader::new(data)
+        .map_err(|error| PiShockError::InvalidAudio(error.to_string()))?;
+
+    pattern_from_wav_reader(reader, options)
+}
+
+fn pattern_from_wav_reader<R: Read>(
+    reader: hound::WavReader<R>,
+    options: AudioOptions,
+) -> Result<Pattern, PiShockError> {
+    options.verify()?;
+
+    let spec = reader.spec();
+    let resolution = options.curve_options.get_resolution();
+    // All channels are mixed together
+    let window_length = ((f64::from(spec.sample_rate) * resolution.as_secs_f64()).round() as usize
+        * usize::from(spec.channels))
+    .max(1);
+
+    let levels = read_levels(reader, window_length, options.mode)?;
+    let loudest = levels.iter().copied().fold(0.0, f32::max);
+
+    Ok(build_pattern(
+        levels.iter().map(|level| {
+            if loudest > 0.0 {
+                options.intensity(level / loudest)
+            } else {
+                None
+            }
+        }),
+        options,
+    ))
+}
+
+/// Decodes the samples to floats from -1.0 to 1.0 and measures the level of each window while reading
+fn read_levels<R: Read>(
+    reader: hound::WavReader<R>,
+    window_length: usize,
+    mode: EnvelopeMode,
+) -> Result<Vec<f32>, PiShockError> {
+    let spec = reader.spec();
+
+    let levels = match spec.sample_format {
+        hound::SampleFormat::Float => {
+            window_levels(reader.into_samples::<f32>(), window_length, mode)
+        }
+        hound::SampleFormat::Int => {
+            let full_scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            window_levels(
+                reader
+                    .into_samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / full_scale)),
+                window_length,
+                mode,
+            )
+        }
+    };
+
+    levels.map_err(|error| PiShockError::InvalidAudio(error.to_string()))
+}
+
+/// Only keeps one level per window, so long files don't have to fit into memory
+fn window_levels(
+    samples: impl Iterator<Item = Result<f32, hound::Error>>,
+    window_length: usize,
+    mode: EnvelopeMode,
+) -> Result<Vec<f32>, hound::Error> {
+    let level = |accumulated: f32, count: usize| match mode {
+        EnvelopeMode::Rms => (accumulated / count as f32).sqrt(),
+        EnvelopeMode::Peak => accumulated,
+    };
+
+    let mut levels = Vec::new();
+    let (mut accumulated, mut count) = (0.0, 0);
+
+    for sample in samples {
+        let sample = sample?;
+        accumulated = match mode {
+            EnvelopeMode::Rms => accumulated + sample * sample,
+            EnvelopeMode::Peak => accumulated.max(sample.abs()),
+        };
+        count += 1;
+
+        if count == window_length {
+            levels.push(level(accumulated, count));
+            (accumulated, count) = (0.0, 0);
+        }
+    }
+
+    // The last window may be shorter
+    if count > 0 {
+        levels.push(level(accumulated, count));
+    }
+
+    Ok(levels)
+}
+
+/// Turns one intensity per window into steps that take exactly one window each when planned
+fn build_pattern(intensities: impl Iterator<Item = Option<u32>>, options: AudioOptions) -> Pattern {
+    let resolution = options.curve_options.get_resolution();
+    let step_gap = options.curve_options.get_step_gap();
+
+    let mut pattern = Pattern::new();
+    let mut silence = Duration::ZERO;
+    let mut after_command = false;
+
+    // The planner only adds the step gap if a pause is shorter, so a pause after a command has to cover it too
+    let pause = |silence: Duration, after_command: bool| {
+        PatternStep::pause(if after_command {
+            silence + step_gap
+        } else {
+            silence
+        })
+    };
+
+    for intensity in intensities {
+        let Some(intensity) = intensity else {
+            silence += resolution;
+            continue;
+        };
+
+        if !silence.is_zero() {
+            pattern.push(pause(silence, after_command));
+            silence = Duration::ZERO;
+        }
+
+        // The step gap is added by the planner before the next command
+        let duration = resolution - step_gap;
+        pattern.push(match options.op {
+            PatternOp::Beep => PatternStep::beep(duration),
+            PatternOp::Pause => PatternStep::pause(duration),
+            op => PatternStep::new(op, intensity, duration),
+        });
+        after_command = true;
+    }
+
+    if !silence.is_zero() {
+        pattern.push(pause(silence, after_command));
+    }
+
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::{pattern_from_wav_data, AudioOptions, EnvelopeMode};
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::{Pattern, PatternStep};
+    use crate::PiShocker;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    /// Writes a mono 8kHz WAV file with one amplitude per 500ms
+    fn wav(amplitudes: &[f32]) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut data, spec).unwrap();
+        for amplitude in amplitudes {
+            for sample in 0..4000 {
+                // A square wave has the same RMS and peak level
+                let sign = if sample % 2 == 0 { 1.0 } else { -1.0 };
+                writer
+                    .write_sample((sign * amplitude * f32::from(i16::MAX)) as i16)
+                    .unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+
+        data.into_inner()
+    }
+
+    #[test]
+    fn envelope_to_pattern() {
+        let pattern = pattern_from_wav_data(
+            Cursor::new(wav(&[0.0, 0.0, 1.0, 0.5, 0.0])),
+            AudioOptions::default().with_floor(10).with_ceiling(50),
+        )
+        .unwrap();
+
+        assert_eq!(
+            pattern,
+            Pattern::new()
+                .with_step(PatternStep::pause(Duration::from_secs(1)))
+                .with_step(PatternStep::vibrate(50, Duration::from_millis(400)))
+                .with_step(PatternStep::vibrate(30, Duration::from_millis(400)))
+                .with_step(PatternStep::pause(Duration::from_millis(600)))
+        );
+
+        // Every window takes exactly the resolution, so the pattern stays in sync with the audio
+        let plan = PiShocker::new("sharecode", "apikey", "username", "pishock_rs")
+            .plan_pattern(&pattern, CurveOptions::default())
+            .unwrap();
+        assert_eq!(plan.get_total_duration(), Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn gamma_and_peak_mode() {
+        let pattern = pattern_from_wav_data(
+            Cursor::new(wav(&[0.5, 1.0])),
+            AudioOptions::default()
+                .with_mode(EnvelopeMode::Peak)
+                .with_gamma(2.0),
+        )
+        .unwrap();
+
+        let intensities: Vec<u32> = pattern
+            .get_steps()
+            .iter()
+            .map(PatternStep::get_intensity)
+            .collect();
+        assert_eq!(intensities, vec![26, 100]);
+    }
+}
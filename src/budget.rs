@@ -0,0 +1,679 @@
+//! Rolling budgets that cap the cumulative stimulus a shocker delivers over a time window.
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pishock_rs::budget::{ExceededPolicy, ExposureBudget};
+//! use pishock_rs::PiShocker;
+//!
+//! let mut pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+//!
+//! // No more than 30 intensity-seconds of shock per 10 minutes, vibrate instead once it is used up
+//! pishocker_instance.add_exposure_budget(
+//!     ExposureBudget::new(Duration::from_secs(10 * 60))
+//!         .with_max_intensity_seconds(30.0)
+//!         .with_exceeded_policy(ExceededPolicy::DowngradeToVibrate),
+//! );
+//! // And at most 20 shocks per hour
+//! pishocker_instance.add_exposure_budget(
+//!     ExposureBudget::new(Duration::from_secs(60 * 60)).with_max_count(20),
+//! );
+//! ```
+
+use crate::errors::PiShockError;
+use crate::validation::Command;
+use crate::{PiShockOpCode, PiShocker};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What happens to a command that would exceed an [`ExposureBudget`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum ExceededPolicy {
+    /// Fails with [`PiShockError::ExposureBudgetExceeded`]
+    #[default]
+    Reject,
+    /// Sends a shock as a vibration with the same intensity and duration instead, other operations are rejected
+    DowngradeToVibrate,
+}
+
+/// A cap on the commands of one operation sent within a rolling time window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureBudget {
+    op: PiShockOpCode,
+    window: Duration,
+    max_intensity_seconds: Option<f32>,
+    max_count: Option<u32>,
+    exceeded_policy: ExceededPolicy,
+}
+
+impl ExposureBudget {
+    /// Creates a budget for shocks over the given window, without any caps yet
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            op: PiShockOpCode::Shock,
+            window,
+            max_intensity_seconds: None,
+            max_count: None,
+            exceeded_policy: ExceededPolicy::Reject,
+        }
+    }
+
+    /// Sets the operation the budget applies to (default [`PiShockOpCode::Shock`])
+    #[must_use]
+    pub fn with_op(mut self, op: PiShockOpCode) -> Self {
+        self.op = op;
+        self
+    }
+
+    /// Caps the sum of intensity times duration in seconds, a shock at 20 for 1.5s uses 30 intensity-seconds
+    #[must_use]
+    pub fn with_max_intensity_seconds(mut self, max_intensity_seconds: f32) -> Self {
+        self.max_intensity_seconds = Some(max_intensity_seconds);
+        self
+    }
+
+    /// Caps the number of commands
+    #[must_use]
+    pub fn with_max_count(mut self, max_count: u32) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    /// Sets what happens to commands that would exceed the budget (default [`ExceededPolicy::Reject`])
+    #[must_use]
+    pub fn with_exceeded_policy(mut self, exceeded_policy: ExceededPolicy) -> Self {
+        self.exceeded_policy = exceeded_policy;
+        self
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> PiShockOpCode {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_window(&self) -> Duration {
+        self.window
+    }
+
+    #[must_use]
+    pub fn get_max_intensity_seconds(&self) -> Option<f32> {
+        self.max_intensity_seconds
+    }
+
+    #[must_use]
+    pub fn get_max_count(&self) -> Option<u32> {
+        self.max_count
+    }
+
+    #[must_use]
+    pub fn get_exceeded_policy(&self) -> ExceededPolicy {
+        self.exceeded_policy
+    }
+
+    /// Returns whether the command fits into the budget, given what was already used
+    fn allows(&self, usage: ExposureUsage, command: &Command) -> bool {
+        if command.get_op() != self.op {
+            return true;
+        }
+
+        let within_intensity = self
+            .max_intensity_seconds
+            .is_none_or(|max| usage.intensity_seconds + intensity_seconds(command) <= max);
+        let within_count = self.max_count.is_none_or(|max| usage.count < max);
+
+        within_intensity && within_count
+    }
+}
+
+/// What a shocker used of an [`ExposureBudget`] so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExposureUsage {
+    intensity_seconds: f32,
+    count: u32,
+}
+
+impl ExposureUsage {
+    #[must_use]
+    pub fn get_intensity_seconds(&self) -> f32 {
+        self.intensity_seconds
+    }
+
+    #[must_use]
+    pub fn get_count(&self) -> u32 {
+        self.count
+    }
+}
+
+/// A command that was sent, with wall clock time so it can be persisted
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct ExposureEntry {
+    op: PiShockOpCode,
+    intensity: u32,
+    duration_ms: u64,
+    sent_at_ms: u64,
+}
+
+/// The commands sent within the longest budget window, shared by all clones of a shocker
+#[derive(Debug, Default)]
+pub(crate) struct ExposureLedger {
+    entries: VecDeque<ExposureEntry>,
+    path: Option<PathBuf>,
+}
+
+/// A command charged to the budgets before it is sent, refunded if sending it fails
+#[derive(Debug)]
+pub(crate) struct ExposureCharge {
+    op: PiShockOpCode,
+    entry: Option<ExposureEntry>,
+}
+
+impl ExposureCharge {
+    /// The operation to send, a shock may have been downgraded to a vibration
+    pub(crate) fn get_op(&self) -> PiShockOpCode {
+        self.op
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExposureFile {
+    entries: Vec<ExposureEntry>,
+}
+
+impl ExposureLedger {
+    fn usage(&self, budget: &ExposureBudget, now_ms: u64) -> ExposureUsage {
+        let window_start = now_ms.saturating_sub(budget.window.as_millis() as u64);
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.op == budget.op && entry.sent_at_ms > window_start)
+            .fold(ExposureUsage::default(), |usage, entry| ExposureUsage {
+                intensity_seconds: usage.intensity_seconds
+                    + entry.intensity as f32 * entry.duration_ms as f32 / 1000.0,
+                count: usage.count + 1,
+            })
+    }
+
+    /// Returns the first budget the command would exceed
+    fn exceeded<'a>(
+        &self,
+        budgets: &'a [ExposureBudget],
+        command: &Command,
+        now_ms: u64,
+    ) -> Option<&'a ExposureBudget> {
+        budgets
+            .iter()
+            .find(|budget| !budget.allows(self.usage(budget, now_ms), command))
+    }
+
+    fn record(
+        &mut self,
+        budgets: &[ExposureBudget],
+        command: &Command,
+        now_ms: u64,
+    ) -> Result<ExposureEntry, PiShockError> {
+        // Nothing older than the longest window can count towards a budget anymore
+        let longest_window = budgets
+            .iter()
+            .map(|budget| budget.window.as_millis() as u64)
+            .max()
+            .unwrap_or(0);
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| entry.sent_at_ms <= now_ms.saturating_sub(longest_window))
+        {
+            self.entries.pop_front();
+        }
+
+        let entry = ExposureEntry {
+            op: command.get_op(),
+            intensity: command.get_intensity(),
+            duration_ms: command.get_duration().as_millis() as u64,
+            sent_at_ms: now_ms,
+        };
+        self.entries.push_back(entry);
+
+        // A command that couldn't be persisted isn't sent, so it mustn't count either
+        if let Err(error) = self.save() {
+            self.entries.pop_back();
+            return Err(error);
+        }
+
+        Ok(entry)
+    }
+
+    /// Checks a command against the budgets and records it, `vibration_error` is why a shock can't be downgraded
+    fn charge(
+        &mut self,
+        budgets: &[ExposureBudget],
+        mut command: Command,
+        vibration_error: Option<PiShockError>,
+        now_ms: u64,
+    ) -> Result<ExposureCharge, PiShockError> {
+        if let Some(budget) = self.exceeded(budgets, &command, now_ms) {
+            let exceeded = PiShockError::ExposureBudgetExceeded {
+                op: budget.op,
+                window: budget.window,
+            };
+
+            if budget.exceeded_policy != ExceededPolicy::DowngradeToVibrate
+                || command.get_op() != PiShockOpCode::Shock
+            {
+                return Err(exceeded);
+            }
+
+            warn!("Exposure budget used up, sending a vibration instead of the shock");
+            command = Command::vibrate(command.get_intensity(), command.get_duration());
+
+            if let Some(error) = vibration_error {
+                return Err(error);
+            }
+            if self.exceeded(budgets, &command, now_ms).is_some() {
+                return Err(exceeded);
+            }
+        }
+
+        let entry = self.record(budgets, &command, now_ms)?;
+
+        Ok(ExposureCharge {
+            op: command.get_op(),
+            entry: Some(entry),
+        })
+    }
+
+    fn remove(&mut self, entry: &ExposureEntry) -> Result<(), PiShockError> {
+        if let Some(index) = self.entries.iter().rposition(|recorded| recorded == entry) {
+            self.entries.remove(index);
+        }
+
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), PiShockError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let file = ExposureFile {
+            entries: self.entries.iter().copied().collect(),
+        };
+        let json = serde_json::to_string(&file)
+            .map_err(|error| PiShockError::InvalidExposureState(error.to_string()))?;
+
+        std::fs::write(path, json)
+            .map_err(|error| PiShockError::InvalidExposureState(error.to_string()))
+    }
+}
+
+fn intensity_seconds(command: &Command) -> f32 {
+    command.get_intensity() as f32 * command.get_duration().as_secs_f32()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl PiShocker {
+    /// Adds a budget that every command is checked against before it is sent.
+    ///
+    /// Budgets are checked for single commands, a pattern that runs out of budget halfway stops
+    /// with [`PiShockError::ExposureBudgetExceeded`] (or continues with vibrations, depending on the policy).
+    pub fn add_exposure_budget(&mut self, budget: ExposureBudget) {
+        self.exposure_budgets.push(budget);
+    }
+
+    /// Returns the exposure budgets of the shocker
+    #[must_use]
+    pub fn get_exposure_budgets(&self) -> &[ExposureBudget] {
+        &self.exposure_budgets
+    }
+
+    /// Returns how much of a budget was used within its current window
+    #[must_use]
+    pub fn get_exposure_usage(&self, budget: &ExposureBudget) -> ExposureUsage {
+        self.exposure.lock().unwrap().usage(budget, now_ms())
+    }
+
+    /// Keeps the sent commands in a file, so restarting the app doesn't reset the budgets.
+    ///
+    /// Commands already recorded in the file are loaded, the file is created if it doesn't exist and
+    /// rewritten after every command.
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidExposureState`] if the file can't be read, parsed or written.
+    pub fn persist_exposure<P: AsRef<Path>>(&self, path: P) -> Result<(), PiShockError> {
+        let path = path.as_ref();
+        let mut ledger = self.exposure.lock().unwrap();
+
+        if path.exists() {
+            let json = std::fs::read_to_string(path)
+                .map_err(|error| PiShockError::InvalidExposureState(error.to_string()))?;
+            let file: ExposureFile = serde_json::from_str(&json)
+                .map_err(|error| PiShockError::InvalidExposureState(error.to_string()))?;
+
+            debug!("Loaded {} exposure entries", file.entries.len());
+            // The file may already contain the commands in memory, e.g. if a clone persisted to it.
+            // Each loaded entry only stands for one of them, identical commands sent at once all count.
+            let mut unmatched = file.entries.clone();
+            let mut entries = file.entries;
+            for entry in ledger.entries.drain(..) {
+                match unmatched.iter().position(|loaded| *loaded == entry) {
+                    Some(index) => {
+                        unmatched.swap_remove(index);
+                    }
+                    None => entries.push(entry),
+                }
+            }
+            entries.sort_by_key(|entry| entry.sent_at_ms);
+            ledger.entries = entries.into();
+        }
+
+        ledger.path = Some(path.to_path_buf());
+        ledger.save()
+    }
+
+    /// Checks a command against the exposure budgets and records it, returning the operation to send.
+    ///
+    /// Checking and recording happens atomically, so clones sending at the same time can't both use up the rest of a budget.
+    /// If the command isn't sent after all, the charge has to be passed to [`PiShocker::refund_exposure`].
+    pub(crate) async fn charge_exposure(
+        &self,
+        op_code: PiShockOpCode,
+        intensity: u32,
+        duration: Duration,
+    ) -> Result<ExposureCharge, PiShockError> {
+        if self.exposure_budgets.is_empty() {
+            return Ok(ExposureCharge {
+                op: op_code,
+                entry: None,
+            });
+        }
+
+        // A shock that is downgraded is sent as a vibration, which has to be allowed on its own
+        let may_downgrade = op_code == PiShockOpCode::Shock
+            && self
+                .exposure_budgets
+                .iter()
+                .any(|budget| budget.exceeded_policy == ExceededPolicy::DowngradeToVibrate);
+        let vibration_error = may_downgrade
+            .then(|| {
+                self.validate(&Command::vibrate(intensity, duration))
+                    .into_iter()
+                    .next()
+                    .map(|violation| self.violation_to_error(violation))
+            })
+            .flatten();
+
+        let ledger = Arc::clone(&self.exposure);
+        let budgets = self.exposure_budgets.clone();
+        let command = Command::new(op_code, intensity, duration);
+
+        // Recording writes the persisted ledger, which mustn't block the async runtime
+        tokio::task::spawn_blocking(move || {
+            ledger
+                .lock()
+                .unwrap()
+                .charge(&budgets, command, vibration_error, now_ms())
+        })
+        .await
+        .map_err(|error| PiShockError::InvalidExposureState(error.to_string()))?
+    }
+
+    /// Removes a command that couldn't be sent from the budgets again
+    pub(crate) async fn refund_exposure(&self, charge: &ExposureCharge) {
+        let Some(entry) = charge.entry else {
+            return;
+        };
+
+        let ledger = Arc::clone(&self.exposure);
+        let refunded = tokio::task::spawn_blocking(move || ledger.lock().unwrap().remove(&entry))
+            .await
+            .map_err(|error| PiShockError::InvalidExposureState(error.to_string()))
+            .and_then(|refunded| refunded);
+
+        if let Err(error) = refunded {
+            warn!("Failed to refund an exposure budget charge: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::budget::{now_ms, ExceededPolicy, ExposureBudget, ExposureEntry};
+    use crate::errors::PiShockError;
+    use crate::{PiShockAccount, PiShockOpCode, PiShocker};
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+    use test_log::test;
+
+    async fn shocker(mockserver: &MockServer, budget: ExposureBudget) -> PiShocker {
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+        pishocker_instance.add_exposure_budget(budget);
+        pishocker_instance
+    }
+
+    #[test(tokio::test)]
+    async fn count_is_shared_across_clones() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishocker_instance = shocker(
+            &mockserver,
+            ExposureBudget::new(Duration::from_secs(60 * 60)).with_max_count(2),
+        )
+        .await;
+        let clone = pishocker_instance.clone();
+
+        pishocker_instance.mini_shock(10).await.unwrap();
+        clone.mini_shock(10).await.unwrap();
+        // Vibrations don't count towards a shock budget
+        clone.vibrate(10, Duration::from_secs(1)).await.unwrap();
+
+        assert!(matches!(
+            pishocker_instance.mini_shock(10).await,
+            Err(PiShockError::ExposureBudgetExceeded {
+                op: PiShockOpCode::Shock,
+                ..
+            })
+        ));
+        assert_eq!(
+            clone
+                .get_exposure_usage(&pishocker_instance.get_exposure_budgets()[0])
+                .get_count(),
+            2
+        );
+        mock.assert_hits(3);
+    }
+
+    #[test(tokio::test)]
+    async fn downgrade_to_vibrate() {
+        let mockserver = MockServer::start();
+        let vibrate_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 1 }).to_string());
+            then.status(200).body("Operation Succeeded.");
+        });
+        let shock_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 0 }).to_string());
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishocker_instance = shocker(
+            &mockserver,
+            ExposureBudget::new(Duration::from_secs(10 * 60))
+                .with_max_intensity_seconds(30.0)
+                .with_exceeded_policy(ExceededPolicy::DowngradeToVibrate),
+        )
+        .await;
+
+        pishocker_instance
+            .shock(20, Duration::from_secs(1))
+            .await
+            .unwrap();
+        pishocker_instance
+            .shock(20, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        shock_mock.assert_hits(1);
+        vibrate_mock.assert_hits(1);
+    }
+
+    #[test(tokio::test)]
+    async fn failed_commands_are_refunded() {
+        let mockserver = MockServer::start();
+        let failing_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Intensity": 30 }).to_string());
+            then.status(200).body("Device in Use.");
+        });
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishocker_instance = shocker(
+            &mockserver,
+            ExposureBudget::new(Duration::from_secs(60 * 60)).with_max_count(1),
+        )
+        .await;
+
+        assert!(matches!(
+            pishocker_instance.mini_shock(30).await,
+            Err(PiShockError::ShockerBusy)
+        ));
+        assert_eq!(
+            pishocker_instance
+                .get_exposure_usage(&pishocker_instance.get_exposure_budgets()[0])
+                .get_count(),
+            0
+        );
+
+        pishocker_instance.mini_shock(10).await.unwrap();
+        failing_mock.assert_hits(1);
+        mock.assert_hits(1);
+    }
+
+    #[test(tokio::test)]
+    async fn unsaved_commands_are_not_counted() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+        let path = std::env::temp_dir().join(format!(
+            "pishock_rs_unsaved_exposure_{}.json",
+            std::process::id()
+        ));
+        let budget = ExposureBudget::new(Duration::from_secs(60 * 60)).with_max_count(1);
+
+        let pishocker_instance = shocker(&mockserver, budget).await;
+        pishocker_instance.persist_exposure(&path).unwrap();
+        // A directory in place of the file makes every write fail
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+
+        let result = pishocker_instance.mini_shock(10).await;
+        std::fs::remove_dir(&path).unwrap();
+
+        assert!(matches!(result, Err(PiShockError::InvalidExposureState(_))));
+        assert_eq!(
+            pishocker_instance.get_exposure_usage(&budget).get_count(),
+            0
+        );
+        mock.assert_hits(0);
+    }
+
+    #[test(tokio::test)]
+    async fn persisted_across_restarts() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+        let path =
+            std::env::temp_dir().join(format!("pishock_rs_exposure_{}.json", std::process::id()));
+        let budget = ExposureBudget::new(Duration::from_secs(60 * 60)).with_max_count(1);
+
+        let pishocker_instance = shocker(&mockserver, budget).await;
+        pishocker_instance.persist_exposure(&path).unwrap();
+        pishocker_instance.mini_shock(10).await.unwrap();
+        // Loading the same file again doesn't count the shock twice
+        pishocker_instance.clone().persist_exposure(&path).unwrap();
+        assert_eq!(
+            pishocker_instance.get_exposure_usage(&budget).get_count(),
+            1
+        );
+
+        // A new instance, as if the app was restarted
+        let restarted = shocker(&mockserver, budget).await;
+        restarted.persist_exposure(&path).unwrap();
+        let result = restarted.mini_shock(10).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(PiShockError::ExposureBudgetExceeded { .. })
+        ));
+        mock.assert_hits(1);
+    }
+
+    #[test(tokio::test)]
+    async fn identical_commands_all_count() {
+        let mockserver = MockServer::start();
+        let path = std::env::temp_dir().join(format!(
+            "pishock_rs_identical_exposure_{}.json",
+            std::process::id()
+        ));
+        let budget = ExposureBudget::new(Duration::from_secs(60 * 60)).with_max_count(5);
+
+        // Two identical shocks sent within the same millisecond
+        let entry = ExposureEntry {
+            op: PiShockOpCode::Shock,
+            intensity: 10,
+            duration_ms: 300,
+            sent_at_ms: now_ms(),
+        };
+        let pishocker_instance = shocker(&mockserver, budget).await;
+        pishocker_instance
+            .exposure
+            .lock()
+            .unwrap()
+            .entries
+            .extend([entry, entry]);
+
+        pishocker_instance.persist_exposure(&path).unwrap();
+        pishocker_instance.clone().persist_exposure(&path).unwrap();
+        let restarted = shocker(&mockserver, budget).await;
+        restarted.persist_exposure(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            pishocker_instance.get_exposure_usage(&budget).get_count(),
+            2
+        );
+        assert_eq!(restarted.get_exposure_usage(&budget).get_count(), 2);
+    }
+}
@@ -0,0 +1,419 @@
+//! A consent gate that rejects shocks until the wearer agreed to them for the current session.
+//!
+//! The wearer grants consent, optionally with an expiry and a maximum intensity, and can revoke it at
+//! any time with [`ConsentGate::safeword`]. Gates are cheap to clone and all clones share the same
+//! state, so grant and revoke can be wired to an endpoint of your app. A gate backed by a file with
+//! [`ConsentGate::with_file`] can be controlled from a separate process.
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pishock_rs::consent::{Consent, ConsentGate};
+//! use pishock_rs::PiShocker;
+//!
+//! let consent_gate = ConsentGate::new();
+//! let mut pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+//! pishocker_instance.set_consent_gate(consent_gate.clone());
+//!
+//! // Called when the wearer agrees, e.g. from a button in the app
+//! consent_gate
+//!     .grant(Consent::new().with_expiry(Duration::from_secs(60 * 60)).with_max_intensity(40))
+//!     .unwrap();
+//!
+//! // Revokes consent at once, commands that are already running stop before the next shock
+//! consent_gate.safeword().unwrap();
+//! ```
+
+use crate::errors::PiShockError;
+use crate::{PiShockOpCode, PiShocker};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The terms the wearer agrees to, see [`ConsentGate::grant`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Consent {
+    expiry: Option<Duration>,
+    max_intensity: Option<u32>,
+}
+
+impl Consent {
+    /// Consent without expiry or intensity limit
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets the consent expire this long after it was granted
+    #[must_use]
+    pub fn with_expiry(mut self, expiry: Duration) -> Self {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// Only allows gated commands up to this intensity
+    #[must_use]
+    pub fn with_max_intensity(mut self, max_intensity: u32) -> Self {
+        self.max_intensity = Some(max_intensity);
+        self
+    }
+
+    #[must_use]
+    pub fn get_expiry(&self) -> Option<Duration> {
+        self.expiry
+    }
+
+    #[must_use]
+    pub fn get_max_intensity(&self) -> Option<u32> {
+        self.max_intensity
+    }
+}
+
+/// Consent that was granted, as it is stored by a [`ConsentGate`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConsentGrant {
+    granted_at_ms: u64,
+    expires_at_ms: Option<u64>,
+    max_intensity: Option<u32>,
+}
+
+impl ConsentGrant {
+    #[must_use]
+    pub fn get_granted_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.granted_at_ms)
+    }
+
+    #[must_use]
+    pub fn get_expires_at(&self) -> Option<SystemTime> {
+        self.expires_at_ms
+            .map(|expires_at_ms| UNIX_EPOCH + Duration::from_millis(expires_at_ms))
+    }
+
+    #[must_use]
+    pub fn get_max_intensity(&self) -> Option<u32> {
+        self.max_intensity
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at_ms
+            .is_some_and(|expires_at_ms| now_ms() >= expires_at_ms)
+    }
+}
+
+/// The reason a command was rejected by a [`ConsentGate`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConsentViolation {
+    /// Consent was never granted or was revoked
+    NotGranted,
+    Expired,
+    /// The intensity is above what the wearer agreed to
+    IntensityAboveConsent {
+        max: u32,
+    },
+}
+
+impl fmt::Display for ConsentViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsentViolation::NotGranted => f.write_str("consent was not granted"),
+            ConsentViolation::Expired => f.write_str("consent has expired"),
+            ConsentViolation::IntensityAboveConsent { max } => {
+                write!(f, "consent only covers intensities up to {max}")
+            }
+        }
+    }
+}
+
+/// Where the current grant is kept
+#[derive(Debug, Clone)]
+enum ConsentStore {
+    Memory(Arc<Mutex<Option<ConsentGrant>>>),
+    File(PathBuf),
+}
+
+/// Rejects gated operations unless the wearer granted consent, shocks are always gated
+#[derive(Debug, Clone)]
+pub struct ConsentGate {
+    store: ConsentStore,
+    vibrate_gated: bool,
+    beep_gated: bool,
+}
+
+impl Default for ConsentGate {
+    fn default() -> Self {
+        Self {
+            store: ConsentStore::Memory(Arc::new(Mutex::new(None))),
+            vibrate_gated: false,
+            beep_gated: false,
+        }
+    }
+}
+
+impl ConsentGate {
+    /// Creates a gate that keeps the consent in memory, starting without consent
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a gate that keeps the consent in a file, so another process can grant or revoke it.
+    ///
+    /// A missing or unreadable file counts as no consent.
+    #[must_use]
+    pub fn with_file<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: ConsentStore::File(path.as_ref().to_path_buf()),
+            ..Self::default()
+        }
+    }
+
+    /// Also requires consent for an operation other than shocks, beeps and vibrations are allowed by default
+    #[must_use]
+    pub fn with_gated(mut self, op: PiShockOpCode) -> Self {
+        match op {
+            PiShockOpCode::Shock => {}
+            PiShockOpCode::Vibrate => self.vibrate_gated = true,
+            PiShockOpCode::Beep => self.beep_gated = true,
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn is_gated(&self, op: PiShockOpCode) -> bool {
+        match op {
+            PiShockOpCode::Shock => true,
+            PiShockOpCode::Vibrate => self.vibrate_gated,
+            PiShockOpCode::Beep => self.beep_gated,
+        }
+    }
+
+    /// Grants consent, replacing any earlier grant
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidConsentState`] if a file backed gate can't write its file.
+    pub fn grant(&self, consent: Consent) -> Result<(), PiShockError> {
+        let granted_at_ms = now_ms();
+        let grant = ConsentGrant {
+            granted_at_ms,
+            expires_at_ms: consent
+                .expiry
+                .map(|expiry| granted_at_ms + expiry.as_millis() as u64),
+            max_intensity: consent.max_intensity,
+        };
+
+        info!("Consent granted: {grant:?}");
+        self.store(Some(grant))
+    }
+
+    /// Revokes consent at once
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidConsentState`] if a file backed gate can't write its file.
+    pub fn revoke(&self) -> Result<(), PiShockError> {
+        info!("Consent revoked");
+        self.store(None)
+    }
+
+    /// Revokes consent at once, the same as [`ConsentGate::revoke`]
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidConsentState`] if a file backed gate can't write its file.
+    pub fn safeword(&self) -> Result<(), PiShockError> {
+        warn!("Safeword called");
+        self.revoke()
+    }
+
+    /// Returns the current grant, even if it has expired
+    #[must_use]
+    pub fn get_grant(&self) -> Option<ConsentGrant> {
+        match &self.store {
+            ConsentStore::Memory(grant) => *grant.lock().unwrap(),
+            ConsentStore::File(path) => std::fs::read_to_string(path)
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .flatten(),
+        }
+    }
+
+    /// Returns why a command would be rejected, if it would be
+    #[must_use]
+    pub fn check(&self, op: PiShockOpCode, intensity: u32) -> Option<ConsentViolation> {
+        if !self.is_gated(op) {
+            return None;
+        }
+
+        self.check_grant(self.get_grant().as_ref(), op, intensity)
+    }
+
+    /// Like [`ConsentGate::check`] with a grant read earlier, so a file backed gate isn't read for every step
+    pub(crate) fn check_grant(
+        &self,
+        grant: Option<&ConsentGrant>,
+        op: PiShockOpCode,
+        intensity: u32,
+    ) -> Option<ConsentViolation> {
+        if !self.is_gated(op) {
+            return None;
+        }
+
+        let Some(grant) = grant else {
+            return Some(ConsentViolation::NotGranted);
+        };
+
+        if grant.is_expired() {
+            return Some(ConsentViolation::Expired);
+        }
+
+        match grant.max_intensity {
+            Some(max) if op != PiShockOpCode::Beep && intensity > max => {
+                Some(ConsentViolation::IntensityAboveConsent { max })
+            }
+            _ => None,
+        }
+    }
+
+    fn store(&self, grant: Option<ConsentGrant>) -> Result<(), PiShockError> {
+        match &self.store {
+            ConsentStore::Memory(stored) => {
+                *stored.lock().unwrap() = grant;
+                Ok(())
+            }
+            ConsentStore::File(path) => {
+                let json = serde_json::to_string(&grant)
+                    .map_err(|error| PiShockError::InvalidConsentState(error.to_string()))?;
+
+                std::fs::write(path, json)
+                    .map_err(|error| PiShockError::InvalidConsentState(error.to_string()))
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl PiShocker {
+    /// Sets the consent gate every command of this shocker is checked against
+    pub fn set_consent_gate(&mut self, consent_gate: ConsentGate) {
+        self.consent_gate = Some(consent_gate);
+    }
+
+    /// Returns the consent gate of the shocker, if any
+    #[must_use]
+    pub fn get_consent_gate(&self) -> Option<&ConsentGate> {
+        self.consent_gate.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consent::{Consent, ConsentGate, ConsentViolation};
+    use crate::errors::PiShockError;
+    use crate::{PiShockAccount, PiShockOpCode, PiShocker};
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use std::time::Duration;
+    use test_log::test;
+
+    async fn gated_shocker(mockserver: &MockServer, consent_gate: ConsentGate) -> PiShocker {
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+        pishocker_instance.set_consent_gate(consent_gate);
+        pishocker_instance
+    }
+
+    #[test(tokio::test)]
+    async fn shocks_need_consent() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let consent_gate = ConsentGate::new();
+        let pishocker_instance = gated_shocker(&mockserver, consent_gate.clone()).await;
+
+        assert!(matches!(
+            pishocker_instance.mini_shock(10).await,
+            Err(PiShockError::ConsentRequired(ConsentViolation::NotGranted))
+        ));
+        // The warning vibration isn't sent for a shock without consent
+        assert!(matches!(
+            pishocker_instance
+                .shock_with_warning(10, Duration::from_secs(1))
+                .await,
+            Err(PiShockError::ConsentRequired(ConsentViolation::NotGranted))
+        ));
+        // Vibrations aren't gated by default
+        pishocker_instance
+            .vibrate(50, Duration::from_secs(1))
+            .await
+            .unwrap();
+        mock.assert_hits(1);
+
+        consent_gate
+            .grant(Consent::new().with_max_intensity(30))
+            .unwrap();
+        pishocker_instance.mini_shock(30).await.unwrap();
+        assert!(matches!(
+            pishocker_instance.mini_shock(31).await,
+            Err(PiShockError::ConsentRequired(
+                ConsentViolation::IntensityAboveConsent { max: 30 }
+            ))
+        ));
+        mock.assert_hits(2);
+
+        consent_gate.safeword().unwrap();
+        assert!(matches!(
+            pishocker_instance.mini_shock(10).await,
+            Err(PiShockError::ConsentRequired(ConsentViolation::NotGranted))
+        ));
+        mock.assert_hits(2);
+    }
+
+    #[test]
+    fn expiry_and_gated_operations() {
+        let consent_gate = ConsentGate::new().with_gated(PiShockOpCode::Vibrate);
+        consent_gate
+            .grant(Consent::new().with_expiry(Duration::ZERO))
+            .unwrap();
+
+        assert_eq!(
+            consent_gate.check(PiShockOpCode::Vibrate, 10),
+            Some(ConsentViolation::Expired)
+        );
+        assert_eq!(consent_gate.check(PiShockOpCode::Beep, 0), None);
+    }
+
+    #[test]
+    fn file_shared_between_processes() {
+        let path =
+            std::env::temp_dir().join(format!("pishock_rs_consent_{}.json", std::process::id()));
+        // Stands in for the gate of another process
+        let wearer = ConsentGate::with_file(&path);
+        let app = ConsentGate::with_file(&path);
+
+        assert_eq!(
+            app.check(PiShockOpCode::Shock, 10),
+            Some(ConsentViolation::NotGranted)
+        );
+        wearer.grant(Consent::new()).unwrap();
+        assert_eq!(app.check(PiShockOpCode::Shock, 10), None);
+        wearer.safeword().unwrap();
+        let revoked = app.check(PiShockOpCode::Shock, 10);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(revoked, Some(ConsentViolation::NotGranted));
+    }
+}
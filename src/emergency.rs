@@ -0,0 +1,234 @@
+//! A latch that stops everything at once: running patterns are cancelled and every shocker sharing
+//! the latch rejects commands until it is reset.
+//!
+//! All shockers created by a [`PiShockAccount`] share the account's latch, including their clones.
+//!
+//! ```
+//! use pishock_rs::PiShockAccount;
+//!
+//! let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+//! let emergency_stop = pishock_account.get_emergency_stop();
+//!
+//! emergency_stop.trip();
+//! assert!(emergency_stop.is_tripped());
+//!
+//! // Nothing is sent again until the latch is reset explicitly
+//! emergency_stop.reset();
+//! ```
+
+use crate::errors::PiShockError;
+use crate::{PiShockAccount, PiShockOpCode, PiShocker};
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The command [`PiShocker::emergency_stop`] sends to replace whatever the shocker is currently doing
+const OVERRIDE_INTENSITY: u32 = 1;
+const OVERRIDE_DURATION: Duration = Duration::from_millis(100);
+
+/// A shared emergency stop latch, clones trip and reset the same latch
+#[derive(Debug, Clone)]
+pub struct EmergencyStop {
+    tripped: Arc<watch::Sender<bool>>,
+}
+
+impl Default for EmergencyStop {
+    fn default() -> Self {
+        Self {
+            tripped: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl PartialEq for EmergencyStop {
+    /// Two latches are equal if they are the same latch
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.tripped, &other.tripped)
+    }
+}
+
+impl Eq for EmergencyStop {}
+
+impl EmergencyStop {
+    /// Creates a new latch that isn't tripped
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trips the latch, running patterns stop before their next command and new commands fail
+    /// with [`PiShockError::EmergencyStopped`]
+    pub fn trip(&self) {
+        warn!("Emergency stop tripped");
+        self.tripped.send_replace(true);
+    }
+
+    /// Resets the latch, so commands are accepted again. Cancelled patterns stay cancelled.
+    pub fn reset(&self) {
+        self.tripped.send_replace(false);
+    }
+
+    #[must_use]
+    pub fn is_tripped(&self) -> bool {
+        *self.tripped.borrow()
+    }
+
+    /// Waits until the latch is tripped, returns right away if it already is
+    pub async fn tripped(&self) {
+        let mut receiver = self.tripped.subscribe();
+        // Only fails if the sender was dropped, but this latch keeps it alive
+        let _ = receiver.wait_for(|tripped| *tripped).await;
+    }
+}
+
+impl PiShockAccount {
+    /// Returns the emergency stop latch shared by all shockers of this account
+    #[must_use]
+    pub fn get_emergency_stop(&self) -> EmergencyStop {
+        self.emergency_stop.clone()
+    }
+
+    /// Uses an existing latch, e.g. to stop the shockers of several accounts at once
+    #[must_use]
+    pub fn with_emergency_stop(mut self, emergency_stop: EmergencyStop) -> Self {
+        self.emergency_stop = emergency_stop;
+        self
+    }
+
+    /// Trips the emergency stop of all shockers of this account, see [`EmergencyStop::trip`]
+    pub fn emergency_stop(&self) {
+        self.emergency_stop.trip();
+    }
+}
+
+impl PiShocker {
+    /// Returns the emergency stop latch of this shocker
+    #[must_use]
+    pub fn get_emergency_stop(&self) -> EmergencyStop {
+        self.emergency_stop.clone()
+    }
+
+    /// Shares a latch with this shocker, shockers created by an account already share the account's latch
+    pub fn set_emergency_stop(&mut self, emergency_stop: EmergencyStop) {
+        self.emergency_stop = emergency_stop;
+    }
+
+    /// Trips the emergency stop and optionally sends a minimal command (a 100ms vibration at intensity 1)
+    /// that replaces whatever the shocker is currently doing.
+    ///
+    /// The override ignores the app side checks like cooldowns, budgets and the safety policy.
+    ///
+    /// ```no_run
+    /// # tokio_test::block_on(async {
+    /// use pishock_rs::PiShockAccount;
+    ///
+    /// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+    /// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+    ///
+    /// pishocker_instance.emergency_stop(true).await.expect("Failed to send the override");
+    /// # })
+    /// ```
+    /// # Errors
+    /// The latch is always tripped, an error means the override command couldn't be sent.
+    pub async fn emergency_stop(&self, send_override: bool) -> Result<(), PiShockError> {
+        self.emergency_stop.trip();
+
+        if !send_override {
+            return Ok(());
+        }
+
+        self.send_api_request(
+            PiShockOpCode::Vibrate,
+            OVERRIDE_INTENSITY,
+            OVERRIDE_DURATION,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::CurveOptions;
+    use crate::playback::PlaybackEvent;
+    use crate::validation::{Command, Violation};
+    use crate::PiShockAccount;
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn stops_all_clones_and_patterns() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+        let clone = pishocker_instance.clone();
+
+        let mut playback = pishocker_instance
+            .play("vib 20 1s x5".parse().unwrap(), CurveOptions::default())
+            .unwrap();
+        while !matches!(playback.next_event().await, Some(PlaybackEvent::Sent(_))) {}
+
+        pishock_account.emergency_stop();
+
+        assert!(matches!(
+            playback.wait().await,
+            Err(PiShockError::EmergencyStopped)
+        ));
+        assert!(matches!(
+            clone.beep(Duration::from_secs(1)).await,
+            Err(PiShockError::EmergencyStopped)
+        ));
+        assert_eq!(
+            clone.validate(&Command::beep(Duration::from_secs(1))),
+            vec![Violation::EmergencyStopped]
+        );
+        mock.assert_hits(1);
+
+        // Only an explicit reset lets commands through again
+        pishocker_instance.get_emergency_stop().reset();
+        clone.beep(Duration::from_secs(1)).await.unwrap();
+        mock.assert_hits(2);
+    }
+
+    #[test(tokio::test)]
+    async fn override_command() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/").json_body(json!({
+                "Op": 1,
+                "Intensity": 1,
+                "Duration": 100,
+                "Code": "sharecode",
+                "Apikey": "apikey",
+                "Name": "pishock_rs",
+                "Username": "username"
+            }));
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        pishocker_instance.emergency_stop(true).await.unwrap();
+
+        assert!(pishock_account.get_emergency_stop().is_tripped());
+        mock.assert_hits(1);
+    }
+}
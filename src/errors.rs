@@ -1,4 +1,7 @@
-use log::{debug, error};
+use crate::consent::ConsentViolation;
+use crate::safety::SafetyViolation;
+use crate::PiShockOpCode;
+use log::debug;
 use std::time::Duration;
 use thiserror::Error;
 
@@ -31,6 +34,64 @@ pub enum PiShockError {
     #[error("Shock cooldown exceeded, {:#?} left", .0)]
     /// If a shock is attempted while the cooldown is not over, this error is returned with the remaining cooldown time
     CooldownExceeded(Duration),
+    #[error("Rate limited by the PiShock API, retry after: {:?}", .0)]
+    /// The API rejected the request because of rate limiting, with the `Retry-After` delay if the server sent one
+    RateLimited(Option<Duration>),
+    #[error("PiShock API server error, response code: {}", .0)]
+    /// The API responded with a 5xx status code
+    ServerError(u16),
+    #[error("Invalid curve options: {}", .0)]
+    /// The [`crate::interpolation::CurveOptions`] passed to a curve can't be executed
+    InvalidCurveOptions(String),
+    #[error("Invalid pattern file: {}", .0)]
+    /// A pattern file couldn't be read, parsed or written
+    InvalidPatternFile(String),
+    #[error("Invalid pattern syntax at line {line}, column {column}: {message}")]
+    /// A pattern written in the text syntax couldn't be parsed, positions start at 1
+    InvalidPatternSyntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Export failed: {}", .0)]
+    /// A chart or timeline couldn't be rendered or written
+    ExportFailed(String),
+    #[error("Invalid audio file: {}", .0)]
+    /// An audio file couldn't be read or decoded
+    InvalidAudio(String),
+    #[error("Invalid MIDI file: {}", .0)]
+    /// A MIDI file couldn't be read or parsed
+    InvalidMidi(String),
+    #[error("Unknown preset: {}", .0)]
+    /// There is no preset with the given name
+    UnknownPreset(String),
+    #[error("Morse code at {wpm} WPM is too fast, the device supports at most {max_wpm} WPM")]
+    /// A dot at the requested speed would be shorter than a command or the gap between two commands
+    MorseTooFast { wpm: u32, max_wpm: u32 },
+    #[error("Character {:?} has no morse code", .0)]
+    /// The character that can't be encoded as morse code
+    UnsupportedMorseCharacter(char),
+    #[error("Safety policy violated: {}", .0)]
+    /// The command exceeds a cap of the [`crate::safety::SafetyPolicy`], nothing was sent
+    SafetyPolicyViolation(SafetyViolation),
+    #[error("Exposure budget for {op:?} over {window:?} is used up")]
+    /// The command would exceed an [`crate::budget::ExposureBudget`], nothing was sent
+    ExposureBudgetExceeded { op: PiShockOpCode, window: Duration },
+    #[error("Invalid exposure state: {}", .0)]
+    /// The persisted exposure history couldn't be read, parsed or written
+    InvalidExposureState(String),
+    #[error("Emergency stop is active")]
+    /// The [`crate::emergency::EmergencyStop`] latch was tripped and hasn't been reset yet
+    EmergencyStopped,
+    #[error("Consent required: {}", .0)]
+    /// The [`crate::consent::ConsentGate`] of the shocker doesn't allow the command, nothing was sent
+    ConsentRequired(ConsentViolation),
+    #[error("Invalid consent state: {}", .0)]
+    /// The consent file couldn't be written
+    InvalidConsentState(String),
+    #[error("Shock intensity can't rise from {from} to {to}, at most to {max}")]
+    /// The shock rises more than the [`crate::escalation::EscalationLimiter`] allows, nothing was sent
+    EscalationTooSteep { from: u32, to: u32, max: u32 },
 }
 
 /// Converts possible HTTP responses to the respective `PiShock` errors
@@ -49,7 +110,13 @@ pub(crate) fn error_to_pishock_error<S: Into<String> + Clone>(
         .contains("Intensity must be between 0 and ")
     {
         return Err(PiShockError::InvalidIntensity(
-            error.into().split(' ').last().unwrap().parse().unwrap(),
+            error
+                .into()
+                .split(' ')
+                .next_back()
+                .unwrap()
+                .parse()
+                .unwrap(),
         ));
     }
 
@@ -59,7 +126,13 @@ pub(crate) fn error_to_pishock_error<S: Into<String> + Clone>(
         .contains("Duration must be between 1 and ")
     {
         return Err(PiShockError::InvalidDuration(
-            error.into().split(' ').last().unwrap().parse().unwrap(),
+            error
+                .into()
+                .split(' ')
+                .next_back()
+                .unwrap()
+                .parse()
+                .unwrap(),
         ));
     }
 
@@ -0,0 +1,492 @@
+//! Limits how fast shock intensities can rise, so a shock at 10 isn't followed by one at 90.
+//!
+//! The limiter compares every shock with the previous one, for single calls as well as the steps of
+//! curves and patterns. After an idle period without shocks it starts over.
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pishock_rs::escalation::{EscalationLimiter, EscalationPolicy};
+//! use pishock_rs::PiShocker;
+//!
+//! let mut pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+//! pishocker_instance.set_escalation_limiter(
+//!     EscalationLimiter::new()
+//!         // At most 15 above the previous shock
+//!         .with_max_step(15)
+//!         // And at most 10 more per second since the previous shock
+//!         .with_max_slope(10.0)
+//!         .with_idle_reset(Duration::from_secs(5 * 60))
+//!         .with_policy(EscalationPolicy::Clamp),
+//! );
+//! ```
+
+use crate::errors::PiShockError;
+use crate::{PiShockOpCode, PiShocker};
+use log::debug;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What happens to a shock that rises more than the limiter allows
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum EscalationPolicy {
+    /// Fails with [`PiShockError::EscalationTooSteep`]
+    #[default]
+    Reject,
+    /// Lowers the intensity to the highest one allowed
+    Clamp,
+}
+
+/// Bounds the rise in intensity between consecutive shocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EscalationLimiter {
+    max_step: Option<u32>,
+    max_slope: Option<f32>,
+    idle_reset: Duration,
+    policy: EscalationPolicy,
+}
+
+impl Default for EscalationLimiter {
+    fn default() -> Self {
+        Self {
+            max_step: None,
+            max_slope: None,
+            idle_reset: Duration::from_secs(60),
+            policy: EscalationPolicy::Reject,
+        }
+    }
+}
+
+impl EscalationLimiter {
+    /// Creates a limiter without any bounds yet, that resets after a minute without shocks
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bounds how much higher a shock can be than the previous one
+    #[must_use]
+    pub fn with_max_step(mut self, max_step: u32) -> Self {
+        self.max_step = Some(max_step);
+        self
+    }
+
+    /// Bounds the rise per second since the previous shock was sent
+    #[must_use]
+    pub fn with_max_slope(mut self, max_slope: f32) -> Self {
+        self.max_slope = Some(max_slope);
+        self
+    }
+
+    /// Sets how long after the end of a shock the next one is unrestricted again (default 60 seconds)
+    #[must_use]
+    pub fn with_idle_reset(mut self, idle_reset: Duration) -> Self {
+        self.idle_reset = idle_reset;
+        self
+    }
+
+    /// Sets what happens to shocks that rise too much (default [`EscalationPolicy::Reject`])
+    #[must_use]
+    pub fn with_policy(mut self, policy: EscalationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn get_max_step(&self) -> Option<u32> {
+        self.max_step
+    }
+
+    #[must_use]
+    pub fn get_max_slope(&self) -> Option<f32> {
+        self.max_slope
+    }
+
+    #[must_use]
+    pub fn get_idle_reset(&self) -> Duration {
+        self.idle_reset
+    }
+
+    #[must_use]
+    pub fn get_policy(&self) -> EscalationPolicy {
+        self.policy
+    }
+
+    /// Returns the highest intensity allowed after a shock that was sent `elapsed` ago, None if any is allowed
+    #[must_use]
+    pub fn max_next(&self, previous: &PreviousShock, elapsed: Duration) -> Option<u32> {
+        if elapsed.saturating_sub(previous.duration) >= self.idle_reset {
+            return None;
+        }
+
+        let by_step = self
+            .max_step
+            .map(|max_step| previous.intensity.saturating_add(max_step));
+        let by_slope = self.max_slope.map(|max_slope| {
+            // Float to int casts saturate, so a huge slope only saturates the bound
+            previous
+                .intensity
+                .saturating_add((max_slope.max(0.0) * elapsed.as_secs_f32()).floor() as u32)
+        });
+
+        by_step.into_iter().chain(by_slope).min()
+    }
+
+    /// Applies the policy to a shock, returning the intensity to send
+    pub(crate) fn limit(
+        &self,
+        previous: Option<&PreviousShock>,
+        elapsed: Duration,
+        intensity: u32,
+    ) -> Result<u32, PiShockError> {
+        let Some(previous) = previous else {
+            return Ok(intensity);
+        };
+
+        match self.max_next(previous, elapsed) {
+            Some(max) if intensity > max => match self.policy {
+                EscalationPolicy::Reject => Err(PiShockError::EscalationTooSteep {
+                    from: previous.intensity,
+                    to: intensity,
+                    max,
+                }),
+                EscalationPolicy::Clamp => {
+                    debug!("Clamping shock intensity {intensity} to {max}");
+                    Ok(max.max(1))
+                }
+            },
+            _ => Ok(intensity),
+        }
+    }
+}
+
+/// The last shock a limiter compares with
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PreviousShock {
+    intensity: u32,
+    duration: Duration,
+}
+
+impl PreviousShock {
+    #[must_use]
+    pub fn new(intensity: u32, duration: Duration) -> Self {
+        Self {
+            intensity,
+            duration,
+        }
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl PiShocker {
+    /// Sets the escalation limiter every shock of this shocker is checked against
+    pub fn set_escalation_limiter(&mut self, escalation_limiter: EscalationLimiter) {
+        self.escalation_limiter = Some(escalation_limiter);
+    }
+
+    /// Returns the escalation limiter of the shocker, if any
+    #[must_use]
+    pub fn get_escalation_limiter(&self) -> Option<EscalationLimiter> {
+        self.escalation_limiter
+    }
+
+    /// Checks a shock against the escalation limiter and reserves it as the new baseline.
+    ///
+    /// The previous shock is shared by all clones, like the cooldown. Checking and reserving happens atomically,
+    /// so clones shocking at the same time can't both rise from the same baseline. If the shock isn't sent after all,
+    /// the reservation has to be passed to [`PiShocker::release_escalation`].
+    pub(crate) fn limit_escalation(
+        &self,
+        op_code: PiShockOpCode,
+        intensity: u32,
+        duration: Duration,
+    ) -> Result<EscalationReservation, PiShockError> {
+        let unreserved = EscalationReservation {
+            intensity,
+            replaced: None,
+            reserved: None,
+        };
+
+        let Some(escalation_limiter) = self.escalation_limiter else {
+            return Ok(unreserved);
+        };
+
+        if op_code != PiShockOpCode::Shock {
+            return Ok(unreserved);
+        }
+
+        let mut last_escalation = self.last_escalation.lock().unwrap();
+        let intensity = escalation_limiter.limit(
+            last_escalation.as_ref().map(|(previous, _)| previous),
+            last_escalation
+                .as_ref()
+                .map_or(Duration::ZERO, |(_, sent_at)| sent_at.elapsed()),
+            intensity,
+        )?;
+
+        let reserved = (PreviousShock::new(intensity, duration), Instant::now());
+        Ok(EscalationReservation {
+            intensity,
+            replaced: last_escalation.replace(reserved),
+            reserved: Some(reserved),
+        })
+    }
+
+    /// Restores the baseline from before a shock that wasn't sent as a shock after all
+    pub(crate) fn release_escalation(&self, reservation: &EscalationReservation) {
+        let Some(reserved) = reservation.reserved else {
+            return;
+        };
+
+        // A later shock may have become the baseline in the meantime
+        let mut last_escalation = self.last_escalation.lock().unwrap();
+        if *last_escalation == Some(reserved) {
+            *last_escalation = reservation.replaced;
+        }
+    }
+}
+
+/// A shock reserved as the baseline of the escalation limiter before it is sent
+#[derive(Debug)]
+pub(crate) struct EscalationReservation {
+    intensity: u32,
+    replaced: Option<(PreviousShock, Instant)>,
+    reserved: Option<(PreviousShock, Instant)>,
+}
+
+impl EscalationReservation {
+    /// The intensity to send, a shock may have been lowered by the limiter
+    pub(crate) fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::escalation::{EscalationLimiter, EscalationPolicy, PreviousShock};
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::Pattern;
+    use crate::{PiShockAccount, PiShocker};
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+    use test_log::test;
+    use tokio::time::Instant;
+
+    async fn limited_shocker(mockserver: &MockServer, limiter: EscalationLimiter) -> PiShocker {
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+        pishocker_instance.set_escalation_limiter(limiter);
+        pishocker_instance
+    }
+
+    #[test(tokio::test)]
+    async fn reject_and_reset() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishocker_instance = limited_shocker(
+            &mockserver,
+            EscalationLimiter::new()
+                .with_max_step(20)
+                .with_idle_reset(Duration::from_millis(200)),
+        )
+        .await;
+
+        pishocker_instance.mini_shock(10).await.unwrap();
+        assert!(matches!(
+            pishocker_instance.mini_shock(40).await,
+            Err(PiShockError::EscalationTooSteep {
+                from: 10,
+                to: 40,
+                max: 30
+            })
+        ));
+        // Vibrations aren't limited
+        pishocker_instance
+            .vibrate(90, Duration::from_millis(100))
+            .await
+            .unwrap();
+        pishocker_instance.mini_shock(30).await.unwrap();
+
+        // The shock lasts 300ms, the limiter starts over 200ms after that
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        pishocker_instance.mini_shock(90).await.unwrap();
+
+        mock.assert_hits(4);
+    }
+
+    #[test(tokio::test)]
+    async fn clamp_single_calls() {
+        let mockserver = MockServer::start();
+        let clamped_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 0, "Intensity": 25 }).to_string());
+            then.status(200).body("Operation Succeeded.");
+        });
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 0, "Intensity": 10 }).to_string());
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishocker_instance = limited_shocker(
+            &mockserver,
+            EscalationLimiter::new()
+                .with_max_step(15)
+                .with_policy(EscalationPolicy::Clamp),
+        )
+        .await;
+
+        pishocker_instance.mini_shock(10).await.unwrap();
+        pishocker_instance.mini_shock(90).await.unwrap();
+
+        clamped_mock.assert_hits(1);
+        mock.assert_hits(1);
+    }
+
+    #[test(tokio::test)]
+    async fn failed_shocks_are_not_recorded() {
+        let mockserver = MockServer::start();
+        let failing_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 0, "Intensity": 30 }).to_string());
+            then.status(200).body("Device in Use.");
+        });
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishocker_instance =
+            limited_shocker(&mockserver, EscalationLimiter::new().with_max_step(20)).await;
+
+        pishocker_instance.mini_shock(10).await.unwrap();
+        assert!(matches!(
+            pishocker_instance.mini_shock(30).await,
+            Err(PiShockError::ShockerBusy)
+        ));
+        pishocker_instance.mini_shock(20).await.unwrap();
+
+        // The failed shock didn't raise the baseline
+        assert!(matches!(
+            pishocker_instance.mini_shock(45).await,
+            Err(PiShockError::EscalationTooSteep {
+                from: 20,
+                to: 45,
+                max: 40
+            })
+        ));
+        failing_mock.assert_hits(1);
+        mock.assert_hits(2);
+    }
+
+    #[test(tokio::test)]
+    async fn concurrent_clones_share_the_baseline() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200)
+                .delay(Duration::from_millis(200))
+                .body("Operation Succeeded.");
+        });
+
+        let pishocker_instance =
+            limited_shocker(&mockserver, EscalationLimiter::new().with_max_step(20)).await;
+        let clone = pishocker_instance.clone();
+
+        // The second shock is checked while the first one is still being sent
+        let (first, second) = tokio::join!(pishocker_instance.mini_shock(10), clone.mini_shock(90));
+
+        first.unwrap();
+        assert!(matches!(
+            second,
+            Err(PiShockError::EscalationTooSteep {
+                from: 10,
+                to: 90,
+                max: 30
+            })
+        ));
+        mock.assert_hits(1);
+    }
+
+    #[test]
+    fn huge_slopes_saturate() {
+        let previous = PreviousShock::new(10, Duration::from_secs(1));
+
+        assert_eq!(
+            EscalationLimiter::new()
+                .with_max_slope(f32::INFINITY)
+                .max_next(&previous, Duration::from_secs(2)),
+            Some(u32::MAX)
+        );
+    }
+
+    #[test]
+    fn plans_start_from_the_last_shock() {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.set_escalation_limiter(EscalationLimiter::new().with_max_step(20));
+        *pishocker_instance.last_escalation.lock().unwrap() = Some((
+            PreviousShock::new(10, Duration::from_secs(1)),
+            Instant::now(),
+        ));
+
+        let gentle: Pattern = "shock 30 1s".parse().unwrap();
+        pishocker_instance
+            .plan_pattern(&gentle, CurveOptions::default())
+            .unwrap();
+
+        let jump: Pattern = "shock 90 1s".parse().unwrap();
+        assert!(matches!(
+            pishocker_instance.plan_pattern(&jump, CurveOptions::default()),
+            Err(PiShockError::EscalationTooSteep {
+                from: 10,
+                to: 90,
+                max: 30
+            })
+        ));
+    }
+
+    #[test]
+    fn curves_are_checked_before_sending() {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.set_escalation_limiter(EscalationLimiter::new().with_max_slope(20.0));
+
+        let gentle: Pattern = "shock 10 ramp-> 40 over 2s".parse().unwrap();
+        pishocker_instance
+            .plan_pattern(&gentle, CurveOptions::default())
+            .unwrap();
+
+        let jump: Pattern = "shock 10 1s; shock 90 1s".parse().unwrap();
+        assert!(matches!(
+            pishocker_instance.plan_pattern(&jump, CurveOptions::default()),
+            Err(PiShockError::EscalationTooSteep {
+                from: 10,
+                to: 90,
+                max: 32
+            })
+        ));
+    }
+}
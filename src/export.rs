@@ -0,0 +1,497 @@
+use crate::errors::PiShockError;
+use crate::interpolation::{CurvePlan, PlannedStep};
+use crate::pattern::PatternOp;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Space around the plot area for the axes and their labels, in pixels
+const MARGIN_LEFT: u32 = 40;
+const MARGIN_RIGHT: u32 = 10;
+const MARGIN_TOP: u32 = 10;
+const MARGIN_BOTTOM: u32 = 30;
+
+/// Possible distances between two time axis ticks in seconds, the smallest one that needs at most 10 ticks is used
+const TIME_TICK_STEPS: [u64; 10] = [1, 2, 5, 10, 15, 30, 60, 120, 300, 600];
+
+/// Returns the RGB color segments of an operation are drawn in
+fn op_color(op: PatternOp) -> [u8; 3] {
+    match op {
+        PatternOp::Beep => [0x3b, 0x82, 0xf6],
+        PatternOp::Vibrate => [0x22, 0xc5, 0x5e],
+        PatternOp::Shock => [0xef, 0x44, 0x44],
+        PatternOp::Pause => [0xff, 0xff, 0xff],
+    }
+}
+
+fn op_name(op: PatternOp) -> &'static str {
+    match op {
+        PatternOp::Beep => "beep",
+        PatternOp::Vibrate => "vibrate",
+        PatternOp::Shock => "shock",
+        PatternOp::Pause => "pause",
+    }
+}
+
+/// Maps plan time and intensity to pixel positions
+struct ChartLayout {
+    width: u32,
+    height: u32,
+    total_secs: f32,
+    tick_step: u64,
+}
+
+impl ChartLayout {
+    fn new(plan: &CurvePlan, width: u32, height: u32) -> Result<Self, PiShockError> {
+        if width <= MARGIN_LEFT + MARGIN_RIGHT || height <= MARGIN_TOP + MARGIN_BOTTOM {
+            return Err(PiShockError::ExportFailed(format!(
+                "chart size {width}x{height} is too small, it needs to be larger than {}x{}",
+                MARGIN_LEFT + MARGIN_RIGHT,
+                MARGIN_TOP + MARGIN_BOTTOM
+            )));
+        }
+
+        let total_secs = plan.get_total_duration().as_secs_f32().max(1.0);
+        let tick_step = TIME_TICK_STEPS
+            .into_iter()
+            .find(|step| total_secs / *step as f32 <= 10.0)
+            .unwrap_or(TIME_TICK_STEPS[TIME_TICK_STEPS.len() - 1]);
+
+        Ok(Self {
+            width,
+            height,
+            total_secs,
+            tick_step,
+        })
+    }
+
+    fn plot_width(&self) -> f32 {
+        (self.width - MARGIN_LEFT - MARGIN_RIGHT) as f32
+    }
+
+    fn plot_height(&self) -> f32 {
+        (self.height - MARGIN_TOP - MARGIN_BOTTOM) as f32
+    }
+
+    fn x(&self, time: Duration) -> f32 {
+        MARGIN_LEFT as f32 + time.as_secs_f32() / self.total_secs * self.plot_width()
+    }
+
+    fn y(&self, intensity: u32) -> f32 {
+        MARGIN_TOP as f32 + (1.0 - intensity.min(100) as f32 / 100.0) * self.plot_height()
+    }
+
+    fn time_ticks(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..)
+            .map(|tick| tick * self.tick_step)
+            .take_while(|seconds| *seconds as f32 <= self.total_secs)
+    }
+
+    /// Returns the rectangle `(x, y, width, height)` of a step, beeps have no intensity and span the whole height
+    fn step_rect(&self, step: &PlannedStep) -> (f32, f32, f32, f32) {
+        let intensity = match step.get_op() {
+            PatternOp::Beep => 100,
+            _ => step.get_intensity(),
+        };
+
+        let x = self.x(step.get_offset());
+        let y = self.y(intensity);
+        (
+            x,
+            y,
+            self.x(step.get_offset() + step.get_duration()) - x,
+            self.y(0) - y,
+        )
+    }
+}
+
+fn sent_steps(plan: &CurvePlan) -> impl Iterator<Item = &PlannedStep> {
+    plan.get_steps()
+        .iter()
+        .filter(|step| step.get_op() != PatternOp::Pause)
+}
+
+impl CurvePlan {
+    /// Renders the plan as an SVG chart with one colored bar per command.
+    ///
+    /// Vibrations are green, shocks are red and beeps are drawn as light blue bars over the whole height.
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::ExportFailed`] if the chart is too small to fit the axes.
+    pub fn to_svg(&self, width: u32, height: u32) -> Result<String, PiShockError> {
+        let layout = ChartLayout::new(self, width, height)?;
+        let mut svg = String::new();
+
+        // Writing to a String never fails
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="10">"#
+        );
+        let _ = writeln!(
+            svg,
+            r#"<rect width="{width}" height="{height}" fill="white"/>"#
+        );
+
+        for step in sent_steps(self) {
+            let (x, y, step_width, step_height) = layout.step_rect(step);
+            let [red, green, blue] = op_color(step.get_op());
+            let opacity = if step.get_op() == PatternOp::Beep {
+                0.3
+            } else {
+                1.0
+            };
+
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{x:.2}" y="{y:.2}" width="{step_width:.2}" height="{step_height:.2}" fill="#{red:02x}{green:02x}{blue:02x}" fill-opacity="{opacity}"><title>{} {} for {}ms</title></rect>"##,
+                op_name(step.get_op()),
+                step.get_intensity(),
+                step.get_duration().as_millis()
+            );
+        }
+
+        let (left, right) = (
+            layout.x(Duration::ZERO),
+            MARGIN_LEFT as f32 + layout.plot_width(),
+        );
+        let (top, bottom) = (layout.y(100), layout.y(0));
+        let _ = writeln!(
+            svg,
+            r#"<path d="M{left} {top} V{bottom} H{right}" fill="none" stroke="black"/>"#
+        );
+
+        for intensity in (0..=100).step_by(25) {
+            let y = layout.y(intensity);
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{}" y1="{y}" x2="{left}" y2="{y}" stroke="black"/><text x="{}" y="{}" text-anchor="end">{intensity}</text>"#,
+                left - 4.0,
+                left - 6.0,
+                y + 3.0
+            );
+        }
+
+        for seconds in layout.time_ticks() {
+            let x = layout.x(Duration::from_secs(seconds));
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{x}" y1="{bottom}" x2="{x}" y2="{}" stroke="black"/><text x="{x}" y="{}" text-anchor="middle">{seconds}s</text>"#,
+                bottom + 4.0,
+                bottom + 15.0
+            );
+        }
+
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle">time</text>"#,
+            left + layout.plot_width() / 2.0,
+            height - 2
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="10" y="{}" text-anchor="middle" transform="rotate(-90 10 {})">intensity</text>"#,
+            top + layout.plot_height() / 2.0,
+            top + layout.plot_height() / 2.0
+        );
+        svg.push_str("</svg>\n");
+
+        Ok(svg)
+    }
+
+    /// Renders the same chart as [`CurvePlan::to_svg`] as an encoded PNG image
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::ExportFailed`] if the chart is too small to fit the axes or can't be encoded.
+    pub fn to_png(&self, width: u32, height: u32) -> Result<Vec<u8>, PiShockError> {
+        let layout = ChartLayout::new(self, width, height)?;
+        let mut canvas = Canvas::new(width, height);
+
+        for step in sent_steps(self) {
+            let (x, y, step_width, step_height) = layout.step_rect(step);
+            let alpha = if step.get_op() == PatternOp::Beep {
+                0.3
+            } else {
+                1.0
+            };
+            canvas.fill_rect(
+                x,
+                y,
+                // Keep very short commands visible
+                step_width.max(1.0),
+                step_height,
+                op_color(step.get_op()),
+                alpha,
+            );
+        }
+
+        let (left, bottom) = (layout.x(Duration::ZERO), layout.y(0));
+        canvas.fill_rect(
+            left - 1.0,
+            layout.y(100),
+            1.0,
+            bottom - layout.y(100),
+            [0; 3],
+            1.0,
+        );
+        canvas.fill_rect(
+            left - 1.0,
+            bottom,
+            layout.plot_width() + 1.0,
+            1.0,
+            [0; 3],
+            1.0,
+        );
+
+        for intensity in (0..=100).step_by(25) {
+            let y = layout.y(intensity);
+            canvas.fill_rect(left - 5.0, y, 4.0, 1.0, [0; 3], 1.0);
+            canvas.draw_text_right(&intensity.to_string(), left - 8.0, y - 4.0);
+        }
+
+        for seconds in layout.time_ticks() {
+            let x = layout.x(Duration::from_secs(seconds));
+            canvas.fill_rect(x, bottom, 1.0, 4.0, [0; 3], 1.0);
+            canvas.draw_text_centered(&format!("{seconds}s"), x, bottom + 8.0);
+        }
+
+        canvas.draw_text_centered(
+            "time",
+            left + layout.plot_width() / 2.0,
+            height as f32 - 11.0,
+        );
+        canvas.draw_text_up("intensity", 0.0, layout.y(50));
+
+        canvas.encode()
+    }
+
+    /// Returns the steps as CSV with the columns `index,op,offset_ms,duration_ms,intensity`, pauses included
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("index,op,offset_ms,duration_ms,intensity\n");
+
+        for (index, step) in self.get_steps().iter().enumerate() {
+            let _ = writeln!(
+                csv,
+                "{index},{},{},{},{}",
+                op_name(step.get_op()),
+                step.get_offset().as_millis(),
+                step.get_duration().as_millis(),
+                step.get_intensity()
+            );
+        }
+
+        csv
+    }
+
+    /// Writes the plan to a file, the format is picked by the file extension (`.svg`, `.png` or `.csv`).
+    /// The size is ignored for CSV files.
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::ExportFailed`] if the file can't be rendered or written.
+    pub fn export<P: AsRef<Path>>(
+        &self,
+        path: P,
+        width: u32,
+        height: u32,
+    ) -> Result<(), PiShockError> {
+        let path = path.as_ref();
+        let contents = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("svg") => self.to_svg(width, height)?.into_bytes(),
+            Some("png") => self.to_png(width, height)?,
+            Some("csv") => self.to_csv().into_bytes(),
+            _ => {
+                return Err(PiShockError::ExportFailed(format!(
+                    "unknown file extension of {}, expected .svg, .png or .csv",
+                    path.display()
+                )))
+            }
+        };
+
+        std::fs::write(path, contents)
+            .map_err(|error| PiShockError::ExportFailed(error.to_string()))
+    }
+}
+
+/// Glyphs of a tiny 3x5 pixel font for axis labels, each row is 3 bits with the left pixel in the highest bit
+fn glyph(character: char) -> Option<[u8; 5]> {
+    Some(match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        's' => [0b000, 0b111, 0b100, 0b011, 0b111],
+        'e' => [0b000, 0b111, 0b111, 0b100, 0b111],
+        'i' => [0b010, 0b000, 0b010, 0b010, 0b010],
+        'm' => [0b000, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b000, 0b110, 0b101, 0b101, 0b101],
+        't' => [0b010, 0b111, 0b010, 0b010, 0b011],
+        'y' => [0b000, 0b101, 0b111, 0b001, 0b110],
+        _ => return None,
+    })
+}
+
+/// Font pixels are drawn as squares of this size
+const GLYPH_SCALE: u32 = 2;
+/// Width of a glyph including the space to the next one
+const GLYPH_ADVANCE: u32 = 4 * GLYPH_SCALE;
+
+/// A white RGB image that can be drawn on and encoded as PNG
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0xff; width as usize * height as usize * 3],
+        }
+    }
+
+    /// Blends a color over the pixels covered by the rectangle, parts outside the canvas are ignored
+    fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [u8; 3], alpha: f32) {
+        let clamp_x = |x: f32| (x.round().max(0.0) as u32).min(self.width);
+        let clamp_y = |y: f32| (y.round().max(0.0) as u32).min(self.height);
+
+        for row in clamp_y(y)..clamp_y(y + height) {
+            for column in clamp_x(x)..clamp_x(x + width) {
+                let index = (row as usize * self.width as usize + column as usize) * 3;
+                for (channel, value) in self.pixels[index..index + 3].iter_mut().zip(color) {
+                    *channel = (f32::from(*channel) * (1.0 - alpha) + f32::from(value) * alpha)
+                        .round() as u8;
+                }
+            }
+        }
+    }
+
+    fn draw_text(&mut self, text: &str, x: f32, y: f32) {
+        self.draw_glyphs(text, |along, across| (x + along, y + across));
+    }
+
+    /// Draws text rotated by 90 degrees so it reads from bottom to top, like the SVG intensity label
+    fn draw_text_up(&mut self, text: &str, x: f32, center: f32) {
+        let bottom = center + Self::text_width(text) / 2.0;
+        let scale = GLYPH_SCALE as f32;
+
+        self.draw_glyphs(text, |along, across| (x + across, bottom - along - scale));
+    }
+
+    /// Draws the glyph pixels, `position` maps the distance along and across the text to canvas coordinates
+    fn draw_glyphs<F: Fn(f32, f32) -> (f32, f32)>(&mut self, text: &str, position: F) {
+        let scale = GLYPH_SCALE as f32;
+
+        for (index, character) in text.chars().enumerate() {
+            let Some(rows) = glyph(character) else {
+                continue;
+            };
+            let glyph_start = (index as u32 * GLYPH_ADVANCE) as f32;
+
+            for (row, bits) in rows.into_iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        let (x, y) =
+                            position(glyph_start + column as f32 * scale, row as f32 * scale);
+                        self.fill_rect(x, y, scale, scale, [0; 3], 1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn text_width(text: &str) -> f32 {
+        (text.chars().count() as u32 * GLYPH_ADVANCE).saturating_sub(GLYPH_SCALE) as f32
+    }
+
+    fn draw_text_right(&mut self, text: &str, right: f32, y: f32) {
+        self.draw_text(text, right - Self::text_width(text), y);
+    }
+
+    fn draw_text_centered(&mut self, text: &str, center: f32, y: f32) {
+        self.draw_text(text, center - Self::text_width(text) / 2.0, y);
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, PiShockError> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|error| PiShockError::ExportFailed(error.to_string()))?;
+
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::Pattern;
+    use crate::PiShocker;
+
+    fn example_plan() -> crate::interpolation::CurvePlan {
+        let pattern: Pattern = "beep 500ms; vib 40 1s; wait 1s; shock 20 2s"
+            .parse()
+            .unwrap();
+
+        PiShocker::new("sharecode", "apikey", "username", "pishock_rs")
+            .plan_pattern(&pattern, CurveOptions::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn csv_timeline() {
+        assert_eq!(
+            example_plan().to_csv(),
+            "index,op,offset_ms,duration_ms,intensity\n\
+             0,beep,0,500,0\n\
+             1,vibrate,600,1000,40\n\
+             2,pause,1600,1000,0\n\
+             3,shock,2600,2000,20\n"
+        );
+    }
+
+    #[test]
+    fn svg_has_colored_segments_and_axes() {
+        let svg = example_plan().to_svg(400, 200).unwrap();
+
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("fill=\"#22c55e\"").count(), 1);
+        assert_eq!(svg.matches("fill=\"#ef4444\"").count(), 1);
+        assert_eq!(svg.matches("fill=\"#3b82f6\"").count(), 1);
+        assert!(svg.contains(">4s</text>"));
+        assert!(svg.contains(">100</text>"));
+    }
+
+    #[test]
+    fn png_is_encoded() {
+        let png = example_plan().to_png(400, 200).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        let is_dark = |x: usize, y: usize| pixels[(y * 400 + x) * 3] < 0x80;
+
+        // The axis labels are drawn below the time ticks and left of the intensity ticks
+        assert!((200..260).any(|x| (189..199).any(|y| is_dark(x, y))));
+        assert!((0..10).any(|x| (60..120).any(|y| is_dark(x, y))));
+
+        assert!(matches!(
+            example_plan().to_png(20, 20),
+            Err(PiShockError::ExportFailed(_))
+        ));
+    }
+}
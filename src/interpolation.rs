@@ -1,6 +1,11 @@
 use crate::errors::PiShockError;
+use crate::limits::{next_split_part, LimitAdjustment, LimitPolicy};
+use crate::pattern::{Pattern, PatternOp};
+use crate::validation::MIN_DURATION;
 use crate::PiShocker;
-use log::{debug, info};
+use log::{debug, info, log_enabled, warn, Level};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::time::Duration;
 use textplots::{Chart, Plot, Shape};
 
@@ -8,6 +13,7 @@ use textplots::{Chart, Plot, Shape};
 pub struct ShockPoint {
     duration: Duration,
     intensity: u32,
+    easing: Easing,
 }
 
 impl ShockPoint {
@@ -16,6 +22,99 @@ impl ShockPoint {
         Self {
             duration,
             intensity,
+            easing: Easing::default(),
+        }
+    }
+
+    /// Sets the easing used to reach this point from the previous one
+    #[must_use]
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    #[must_use]
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_easing(&self) -> Easing {
+        self.easing
+    }
+}
+
+/// The shape of the transition from the previous intensity to the intensity of a [`ShockPoint`]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    /// Jumps to the target intensity right away and holds it for the whole segment
+    Step,
+    /// A straight ramp, this is the default
+    #[default]
+    Linear,
+    /// Starts slow and speeds up towards the target (quadratic)
+    EaseIn,
+    /// Starts fast and slows down towards the target (quadratic)
+    EaseOut,
+    /// Slow at both ends and fast in the middle (quadratic)
+    EaseInOut,
+    /// A half cosine wave, smoother than [`Easing::EaseInOut`]
+    Sine,
+    /// A CSS-style cubic bezier curve through (0, 0), (x1, y1), (x2, y2) and (1, 1).
+    /// The x values are clamped to 0..=1, y values may overshoot but intensities stay between the two points.
+    CubicBezier { x1: f32, y1: f32, x2: f32, y2: f32 },
+}
+
+impl PartialEq for Easing {
+    /// Bezier control points are compared bit for bit, so every easing is equal to itself
+    fn eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (
+                Easing::CubicBezier { x1, y1, x2, y2 },
+                Easing::CubicBezier {
+                    x1: other_x1,
+                    y1: other_y1,
+                    x2: other_x2,
+                    y2: other_y2,
+                },
+            ) => [x1, y1, x2, y2]
+                .map(f32::to_bits)
+                .eq(&[other_x1, other_y1, other_x2, other_y2].map(f32::to_bits)),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Easing {}
+
+impl Easing {
+    /// Maps the progress through a segment (0.0 to 1.0) to the progress of the intensity change
+    #[must_use]
+    pub fn apply(&self, progress: f32) -> f32 {
+        let t = progress.clamp(0.0, 1.0);
+
+        match *self {
+            Easing::Step => 1.0,
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::Sine => (1.0 - (t * std::f32::consts::PI).cos()) / 2.0,
+            Easing::CubicBezier { x1, y1, x2, y2 } => {
+                cubic_bezier(x1.clamp(0.0, 1.0), y1, x2.clamp(0.0, 1.0), y2, t)
+            }
         }
     }
 }
@@ -25,91 +124,664 @@ impl ShockPoint {
 static INTERPOLATION_RESOLUTION: u32 = 500;
 static INTERPOLATION_SLEEP_TIME: u32 = 100;
 
-static INTERPOLATION_DEFAULT_START_Y: u32 = 1;
+pub(crate) static INTERPOLATION_DEFAULT_START_Y: u32 = 1;
 
-impl PiShocker {
-    pub async fn shock_curve(&self, points: Vec<ShockPoint>) -> Result<(), PiShockError> {
-        // Verify that all ShockPoints don't exceed duration or intensity limits
-        for point in &points {
-            if let Some(error) = self.max_intensity_error_triggered(point.intensity) {
-                return Err(error);
+/// Settings for turning [`ShockPoint`]s into individual commands.
+///
+/// The defaults are known to work with the public PiShock API, use the `with_*` methods to change them.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CurveOptions {
+    resolution: Duration,
+    step_gap: Duration,
+    start_intensity: u32,
+    limit_policy: LimitPolicy,
+}
+
+impl Default for CurveOptions {
+    fn default() -> Self {
+        Self {
+            resolution: Duration::from_millis(u64::from(INTERPOLATION_RESOLUTION)),
+            step_gap: Duration::from_millis(u64::from(INTERPOLATION_SLEEP_TIME)),
+            start_intensity: INTERPOLATION_DEFAULT_START_Y,
+            limit_policy: LimitPolicy::Reject,
+        }
+    }
+}
+
+impl CurveOptions {
+    /// Sets the length of each interpolated step (default 500ms)
+    ///
+    /// Steps below 500ms are likely to cause [`PiShockError::ShockerBusy`] errors on the public API.
+    #[must_use]
+    pub fn with_resolution(mut self, resolution: Duration) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Sets the pause between two steps (default 100ms)
+    #[must_use]
+    pub fn with_step_gap(mut self, step_gap: Duration) -> Self {
+        self.step_gap = step_gap;
+        self
+    }
+
+    /// Sets the intensity the first segment ramps up from (default 1)
+    #[must_use]
+    pub fn with_start_intensity(mut self, start_intensity: u32) -> Self {
+        self.start_intensity = start_intensity;
+        self
+    }
+
+    /// Sets how steps exceeding the shocker limits are handled (default [`LimitPolicy::Reject`])
+    #[must_use]
+    pub fn with_limit_policy(mut self, limit_policy: LimitPolicy) -> Self {
+        self.limit_policy = limit_policy;
+        self
+    }
+
+    #[must_use]
+    pub fn get_resolution(&self) -> Duration {
+        self.resolution
+    }
+
+    #[must_use]
+    pub fn get_step_gap(&self) -> Duration {
+        self.step_gap
+    }
+
+    #[must_use]
+    pub fn get_start_intensity(&self) -> u32 {
+        self.start_intensity
+    }
+
+    #[must_use]
+    pub fn get_limit_policy(&self) -> LimitPolicy {
+        self.limit_policy
+    }
+
+    /// Rejects options the API can't execute and warns about options that will likely cause busy errors
+    pub(crate) fn verify(&self) -> Result<(), PiShockError> {
+        // Every step is sent as its own command, so it has to be a valid command duration
+        if self.resolution < MIN_DURATION {
+            return Err(PiShockError::InvalidCurveOptions(format!(
+                "resolution must be at least {MIN_DURATION:?}, got {:?}",
+                self.resolution
+            )));
+        }
+
+        if self.start_intensity > 100 {
+            return Err(PiShockError::InvalidCurveOptions(format!(
+                "start intensity must be at most 100, got {}",
+                self.start_intensity
+            )));
+        }
+
+        if self.resolution < Duration::from_millis(u64::from(INTERPOLATION_RESOLUTION)) {
+            warn!(
+                "Curve resolution of {:?} is below {}ms, this will likely cause ShockerBusy errors",
+                self.resolution, INTERPOLATION_RESOLUTION
+            );
+        }
+
+        if self.step_gap < Duration::from_millis(u64::from(INTERPOLATION_SLEEP_TIME)) {
+            warn!(
+                "Curve step gap of {:?} is below {}ms, the firmware requires some delay between commands",
+                self.step_gap, INTERPOLATION_SLEEP_TIME
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// A single step of a [`CurvePlan`], a command to send or a pause to wait for.
+///
+/// The API sends durations of a second or longer in whole seconds, so a command of e.g. 1.5 seconds
+/// only lasts 1 second on the shocker, while the plan keeps its place on the timeline.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PlannedStep {
+    op: PatternOp,
+    intensity: u32,
+    duration: Duration,
+    offset: Duration,
+}
+
+impl PlannedStep {
+    #[must_use]
+    pub fn get_op(&self) -> PatternOp {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the time from the start of the plan until this step starts
+    #[must_use]
+    pub fn get_offset(&self) -> Duration {
+        self.offset
+    }
+}
+
+/// The result of planning a curve or pattern with [`PiShocker::plan_curve`] or
+/// [`PiShocker::plan_pattern`], without sending anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurvePlan {
+    steps: Vec<PlannedStep>,
+    options: CurveOptions,
+    total_duration: Duration,
+    adjustments: Vec<LimitAdjustment>,
+}
+
+/// The steps of a plan computed on demand, see [`PiShocker::plan_steps`].
+///
+/// Only the current segment is kept in memory, so even patterns that run for hours can be played or
+/// inspected without materializing every command. Cloning restarts from the same position.
+#[derive(Debug, Clone)]
+pub struct PlannedSteps<'a> {
+    pattern: Cow<'a, Pattern>,
+    options: CurveOptions,
+    adjustments: Vec<LimitAdjustment>,
+    /// Commands longer than this are split into parts the API can send exactly
+    max_command_duration: Option<Duration>,
+    next_pattern_step: usize,
+    segment: Option<(PatternOp, SegmentSteps)>,
+    /// Operation, intensity and remaining duration of a split command
+    split: Option<(PatternOp, u32, Duration)>,
+    last_intensity: u32,
+    cursor: Duration,
+    /// Time since the last command ended, None before the first command
+    idle: Option<Duration>,
+}
+
+impl<'a> PlannedSteps<'a> {
+    pub(crate) fn new(
+        pattern: Cow<'a, Pattern>,
+        options: CurveOptions,
+        adjustments: Vec<LimitAdjustment>,
+        max_command_duration: Option<Duration>,
+    ) -> Self {
+        Self {
+            pattern,
+            options,
+            adjustments,
+            max_command_duration: max_command_duration.filter(|max| !max.is_zero()),
+            next_pattern_step: 0,
+            segment: None,
+            split: None,
+            last_intensity: options.start_intensity,
+            cursor: Duration::ZERO,
+            idle: None,
+        }
+    }
+
+    /// Returns the options the steps are planned with
+    #[must_use]
+    pub fn get_options(&self) -> CurveOptions {
+        self.options
+    }
+
+    /// Returns the changes the [`LimitPolicy`] made to fit the shocker limits, empty if none were needed
+    #[must_use]
+    pub fn get_adjustments(&self) -> &[LimitAdjustment] {
+        &self.adjustments
+    }
+
+    /// Returns the time from the start of the plan until the end of the last returned step,
+    /// which is the total duration once all steps were returned
+    #[must_use]
+    pub fn get_elapsed(&self) -> Duration {
+        self.cursor
+    }
+
+    /// Renders the remaining steps like [`CurvePlan::render_chart`] without collecting them,
+    /// each column of the chart shows the highest intensity within it
+    #[must_use]
+    pub fn render_chart(&self, width: u32, height: u32) -> String {
+        let start = self.cursor;
+        let mut remaining = self.clone();
+        remaining.by_ref().for_each(drop);
+        let total_duration = remaining.cursor.saturating_sub(start);
+
+        let columns = width.max(1) as usize;
+        let column = |time: Duration| {
+            let position = time.saturating_sub(start).as_secs_f32() / total_duration.as_secs_f32();
+            ((position * columns as f32) as usize).min(columns - 1)
+        };
+
+        let mut peaks = vec![0; columns];
+        if !total_duration.is_zero() {
+            for step in self.clone() {
+                if step.op.op_code().is_none() {
+                    continue;
+                }
+
+                let end = (step.offset + step.duration).saturating_sub(Duration::from_nanos(1));
+                for peak in &mut peaks[column(step.offset)..=column(end)] {
+                    *peak = (*peak).max(step.intensity);
+                }
+            }
+        }
+
+        let shape = Shape::Continuous(Box::new(|x| {
+            peaks[column(start + Duration::from_secs_f32(x.max(0.0)))] as f32
+        }));
+
+        let mut chart = Chart::new(width, height, 0.0, total_duration.as_secs_f32());
+        chart.lineplot(&shape).to_string()
+    }
+
+    /// Returns the next command or pause before it is split and placed on the timeline
+    fn next_unsplit(&mut self) -> Option<(PatternOp, u32, Duration)> {
+        loop {
+            if let Some((op, segment)) = &mut self.segment {
+                if let Some((intensity, duration)) = segment.next() {
+                    self.last_intensity = intensity;
+                    return Some((*op, intensity, duration));
+                }
+                self.segment = None;
             }
 
-            if let Some(error) = self.max_duration_error_triggered(point.duration) {
-                return Err(error);
+            let step = self
+                .pattern
+                .get_steps()
+                .get(self.next_pattern_step)?
+                .clone();
+            self.next_pattern_step += 1;
+
+            if !step.get_op().has_intensity() {
+                return Some((step.get_op(), 0, step.get_duration()));
             }
+
+            self.segment = Some((
+                step.get_op(),
+                SegmentSteps::new(
+                    step.get_from().unwrap_or(self.last_intensity),
+                    step.get_intensity(),
+                    step.get_duration(),
+                    step.get_easing(),
+                    self.options.resolution,
+                ),
+            ));
         }
+    }
 
-        let total_length: Duration = points.iter().map(|point| point.duration).sum();
-        debug!("Total length of raw curve: {:#?}", total_length);
+    fn next_command(&mut self) -> Option<(PatternOp, u32, Duration)> {
+        loop {
+            if let (Some((op, intensity, remaining)), Some(max_duration)) =
+                (&mut self.split, self.max_command_duration)
+            {
+                if let Some(part) = next_split_part(*remaining, max_duration) {
+                    *remaining -= part;
+                    let command = (*op, *intensity, part);
+                    if remaining.is_zero() {
+                        self.split = None;
+                    }
+                    return Some(command);
+                }
+
+                // The rest is too short to send, the API would have cut it off anyway
+                self.split = None;
+            }
+
+            let (op, intensity, duration) = self.next_unsplit()?;
 
-        // Calculate an interpolated curve
-        let mut interpolated_curve: Vec<ShockPoint> = Vec::new();
-
-        // Generate a Vec with shock points that are interpolated between the given points
-        for point in points {
-            let mut time = Duration::default();
-            while time < point.duration {
-                let intensity = linear_interpolation(
-                    interpolated_curve
-                        .last()
-                        .map_or(INTERPOLATION_DEFAULT_START_Y, |point| point.intensity),
-                    point.intensity,
-                    time.as_millis() as u32,
-                    point.duration.as_millis() as u32,
-                );
-
-                interpolated_curve.push(ShockPoint::new(
-                    Duration::from_millis(u64::from(INTERPOLATION_RESOLUTION)),
-                    intensity,
-                ));
-                time += Duration::from_millis(u64::from(INTERPOLATION_RESOLUTION));
+            match self.max_command_duration {
+                Some(max_duration) if op != PatternOp::Pause && duration > max_duration => {
+                    self.split = Some((op, intensity, duration));
+                }
+                _ => return Some((op, intensity, duration)),
             }
         }
+    }
+}
 
-        {
-            let shape = Shape::Continuous(Box::new(|x| {
-                interpolated_curve
-                    .clone()
-                    .get(x as usize)
-                    .map_or(0.0, |point| point.intensity as f32)
-            }));
+impl Iterator for PlannedSteps<'_> {
+    type Item = PlannedStep;
 
-            let mut chart = Chart::new(180, 60, 0 as f32, interpolated_curve.len() as f32);
-            let chart_line = chart.lineplot(&shape);
+    /// Lays out the steps on a timeline, the step gap is kept between two consecutive commands
+    /// unless a pause step already separates them by at least as much.
+    fn next(&mut self) -> Option<PlannedStep> {
+        let (op, intensity, duration) = self.next_command()?;
 
-            info!(
-                "Shock step graph - 1 step = {}ms\n{}",
-                INTERPOLATION_RESOLUTION,
-                chart_line.to_string()
-            );
+        if op == PatternOp::Pause {
+            let step = PlannedStep {
+                op,
+                intensity: 0,
+                duration,
+                offset: self.cursor,
+            };
+            self.cursor += duration;
+            self.idle = self.idle.map(|idle| idle + duration);
+            return Some(step);
+        }
+
+        if let Some(idle) = self.idle {
+            self.cursor += self.options.step_gap.saturating_sub(idle);
+        }
+
+        let step = PlannedStep {
+            op,
+            intensity,
+            duration,
+            offset: self.cursor,
+        };
+        self.cursor += duration;
+        self.idle = Some(Duration::ZERO);
+
+        Some(step)
+    }
+}
+
+impl CurvePlan {
+    /// Collects all steps into a plan
+    pub(crate) fn new(mut steps: PlannedSteps) -> Self {
+        let planned_steps = steps.by_ref().collect();
+
+        Self {
+            steps: planned_steps,
+            options: steps.options,
+            total_duration: steps.cursor,
+            adjustments: steps.adjustments,
         }
+    }
+
+    /// Returns the planned steps in the order they are sent, see [`PlannedStep`] for how durations are sent
+    #[must_use]
+    pub fn get_steps(&self) -> &[PlannedStep] {
+        &self.steps
+    }
+
+    /// Returns the options the plan was created with
+    #[must_use]
+    pub fn get_options(&self) -> CurveOptions {
+        self.options
+    }
+
+    /// Returns the changes the [`LimitPolicy`] made to fit the shocker limits, empty if none were needed
+    #[must_use]
+    pub fn get_adjustments(&self) -> &[LimitAdjustment] {
+        &self.adjustments
+    }
+
+    /// Returns the total wall-clock time of the plan, including the gaps between steps
+    #[must_use]
+    pub fn get_total_duration(&self) -> Duration {
+        self.total_duration
+    }
 
-        #[cfg(debug_assertions)]
-        debug!("Interpolated curve: {:#?}", interpolated_curve);
+    /// Returns the highest intensity of all steps, 0 for an empty plan
+    #[must_use]
+    pub fn get_peak_intensity(&self) -> u32 {
+        self.steps
+            .iter()
+            .map(|step| step.intensity)
+            .max()
+            .unwrap_or(0)
+    }
 
-        debug!(
-            "Total length of interpolated curve: {:#?}",
-            interpolated_curve
+    /// Returns the time-weighted average intensity of all vibrate and shock steps, 0.0 for an empty plan
+    #[must_use]
+    pub fn get_average_intensity(&self) -> f32 {
+        let intensity_steps = || {
+            self.steps
                 .iter()
-                .map(|point| point.duration)
-                .sum::<Duration>()
-        );
+                .filter(|step| matches!(step.op, PatternOp::Vibrate | PatternOp::Shock))
+        };
+
+        let total_time: Duration = intensity_steps().map(|step| step.duration).sum();
+        if total_time.is_zero() {
+            return 0.0;
+        }
+
+        let weighted: f32 = intensity_steps()
+            .map(|step| step.intensity as f32 * step.duration.as_secs_f32())
+            .sum();
+
+        weighted / total_time.as_secs_f32()
+    }
+
+    /// Returns the intensity at the given time, 0 during gaps and pauses
+    #[must_use]
+    pub fn intensity_at(&self, time: Duration) -> u32 {
+        // Steps are sorted by offset, so the step covering `time` is the last one starting before it
+        let index = self.steps.partition_point(|step| step.offset <= time);
 
-        for point in interpolated_curve {
-            debug!(
-                "Sending shock at intensity {} for duration {:#?}",
-                point.intensity, point.duration
+        index
+            .checked_sub(1)
+            .map(|index| self.steps[index])
+            .filter(|step| time < step.offset + step.duration)
+            .map_or(0, |step| step.intensity)
+    }
+
+    /// Renders the intensity over time as an ASCII chart, the x axis is in seconds
+    #[must_use]
+    pub fn render_chart(&self, width: u32, height: u32) -> String {
+        let shape = Shape::Continuous(Box::new(|x| {
+            self.intensity_at(Duration::from_secs_f32(x.max(0.0))) as f32
+        }));
+
+        let mut chart = Chart::new(width, height, 0.0, self.total_duration.as_secs_f32());
+        chart.lineplot(&shape).to_string()
+    }
+}
+
+impl PiShocker {
+    /// Interpolates a curve and checks it against the shocker limits without sending anything.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use pishock_rs::PiShocker;
+    /// # use pishock_rs::interpolation::{CurveOptions, ShockPoint};
+    /// let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+    ///
+    /// let plan = pishocker_instance
+    ///     .plan_curve(
+    ///         &[ShockPoint::new(Duration::from_secs(2), 50)],
+    ///         CurveOptions::default(),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(plan.get_steps().len(), 4);
+    /// assert_eq!(plan.get_total_duration(), Duration::from_millis(2300));
+    /// println!("{}", plan.render_chart(180, 60));
+    /// ```
+    /// # Errors
+    /// Returns [`PiShockError::InvalidCurveOptions`] if the options can't be executed, and the usual
+    /// intensity and duration errors if any point exceeds the shocker limits.
+    pub fn plan_curve(
+        &self,
+        points: &[ShockPoint],
+        options: CurveOptions,
+    ) -> Result<CurvePlan, PiShockError> {
+        let total_length: Duration = points.iter().map(|point| point.duration).sum();
+        debug!("Total length of raw curve: {:#?}", total_length);
+
+        self.plan_pattern(&Pattern::from(points.to_vec()), options)
+    }
+
+    /// Sends a curve of shocks, interpolating between the given points.
+    ///
+    /// Accepts a `Vec<ShockPoint>` or any [`Pattern`], e.g. one from [`crate::random`].
+    /// Use [`CurveOptions::default`] unless your transport or device needs different timings.
+    /// To preview a curve without sending it, use [`PiShocker::plan_curve`].
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidCurveOptions`] if the options can't be executed, and the usual
+    /// intensity and duration errors if any point exceeds the shocker limits.
+    pub async fn shock_curve<P: Into<Pattern>>(
+        &self,
+        points: P,
+        options: CurveOptions,
+    ) -> Result<(), PiShockError> {
+        let pattern = points.into();
+        let steps = self.plan_steps(&pattern, options)?;
+
+        // Charting walks the whole plan, only do it if the chart is shown
+        if log_enabled!(Level::Info) {
+            info!(
+                "Shock step graph - x axis in seconds\n{}",
+                steps.render_chart(180, 60)
             );
-            self.shock(point.intensity, point.duration).await?;
-            tokio::time::sleep(Duration::from_millis(u64::from(INTERPOLATION_SLEEP_TIME))).await;
         }
+
+        self.play_steps(steps).await?;
         debug!("Finished sending shock curve");
 
         Ok(())
     }
 }
 
+/// Interpolates a single segment from `start` to `end` into steps of `resolution` length, computed on demand.
+///
+/// [`Easing::Step`] segments are constant, so they yield a single step covering the whole segment.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use pishock_rs::interpolation::{Easing, SegmentSteps};
+/// let steps: Vec<(u32, Duration)> = SegmentSteps::new(
+///     10,
+///     50,
+///     Duration::from_secs(2),
+///     Easing::EaseIn,
+///     Duration::from_millis(500),
+/// )
+/// .collect();
+///
+/// assert_eq!(steps.len(), 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SegmentSteps {
+    start: u32,
+    end: u32,
+    duration: Duration,
+    easing: Easing,
+    resolution: Duration,
+    time: Duration,
+    previous: Option<u32>,
+}
+
+impl SegmentSteps {
+    /// Creates the steps of a segment, resolutions below 100ms are raised to 100ms
+    #[must_use]
+    pub fn new(
+        start: u32,
+        end: u32,
+        duration: Duration,
+        easing: Easing,
+        resolution: Duration,
+    ) -> Self {
+        Self {
+            start,
+            end,
+            duration,
+            easing,
+            resolution: resolution.max(MIN_DURATION),
+            time: Duration::ZERO,
+            previous: None,
+        }
+    }
+}
+
+impl Iterator for SegmentSteps {
+    type Item = (u32, Duration);
+
+    fn next(&mut self) -> Option<(u32, Duration)> {
+        if self.easing == Easing::Step {
+            if self.previous.is_some() {
+                return None;
+            }
+
+            self.previous = Some(self.end);
+            return Some((self.end, self.duration));
+        }
+
+        if self.time >= self.duration {
+            return None;
+        }
+
+        // Linear ramps have always continued from the previous step, keep it that way so existing
+        // curves don't change. The other easings need the segment start to keep their shape.
+        let from = match self.easing {
+            Easing::Linear => self.previous.unwrap_or(self.start),
+            _ => self.start,
+        };
+
+        let intensity = eased_interpolation(
+            self.easing,
+            from,
+            self.end,
+            self.time.as_millis() as u32,
+            self.duration.as_millis() as u32,
+        );
+
+        self.previous = Some(intensity);
+        self.time += self.resolution;
+
+        Some((intensity, self.resolution))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.easing == Easing::Step {
+            usize::from(self.previous.is_none())
+        } else {
+            self.duration
+                .saturating_sub(self.time)
+                .as_nanos()
+                .div_ceil(self.resolution.as_nanos()) as usize
+        };
+
+        (remaining, Some(remaining))
+    }
+}
+
+fn eased_interpolation(easing: Easing, start: u32, end: u32, time: u32, duration: u32) -> u32 {
+    // Keep the integer math for linear ramps so existing curves stay exactly the same
+    if easing == Easing::Linear {
+        return linear_interpolation(start, end, time, duration);
+    }
+
+    let progress = easing.apply(time as f32 / duration as f32);
+    let result = start as f32 + (end as f32 - start as f32) * progress;
+
+    debug!(
+        "{:?} interpolation: start: {}, end: {}, time: {}, duration: {}, result: {}",
+        easing, start, end, time, duration, result
+    );
+
+    // Bezier curves may overshoot, but the intensity never leaves the range between the two points
+    result
+        .round()
+        .clamp(start.min(end) as f32, start.max(end) as f32) as u32
+}
+
+/// Evaluates a cubic bezier easing curve at x = `progress` by solving for the curve parameter
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, progress: f32) -> f32 {
+    // One dimension of a bezier curve with the end points fixed at 0 and 1
+    let bezier = |p1: f32, p2: f32, s: f32| {
+        3.0 * (1.0 - s).powi(2) * s * p1 + 3.0 * (1.0 - s) * s * s * p2 + s.powi(3)
+    };
+
+    // x(s) is monotonic because the x control points are within 0..=1, so bisection always converges
+    let (mut low, mut high) = (0.0_f32, 1.0_f32);
+    for _ in 0..32 {
+        let middle = (low + high) / 2.0;
+        if bezier(x1, x2, middle) < progress {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    bezier(y1, y2, (low + high) / 2.0)
+}
+
 fn linear_interpolation(start: u32, end: u32, time: u32, duration: u32) -> u32 {
     // Convert to signed to allow for negative values that will
     // be present during downward trends
@@ -128,3 +800,157 @@ fn linear_interpolation(start: u32, end: u32, time: u32, duration: u32) -> u32 {
     // The absolute intensity must always be a positive value
     result.unsigned_abs()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::{
+        eased_interpolation, CurveOptions, Easing, SegmentSteps, ShockPoint,
+    };
+    use crate::pattern::Pattern;
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    #[test]
+    fn easing_end_points() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+            Easing::Sine,
+            Easing::CubicBezier {
+                x1: 0.42,
+                y1: 0.0,
+                x2: 0.58,
+                y2: 1.0,
+            },
+        ] {
+            assert!(easing.apply(0.0).abs() < 0.001, "{easing:?} at 0");
+            assert!((easing.apply(1.0) - 1.0).abs() < 0.001, "{easing:?} at 1");
+        }
+    }
+
+    #[test]
+    fn easing_shapes() {
+        assert_eq!(eased_interpolation(Easing::Step, 10, 90, 0, 1000), 90);
+        assert_eq!(eased_interpolation(Easing::Linear, 10, 90, 500, 1000), 50);
+        assert!(eased_interpolation(Easing::EaseIn, 10, 90, 500, 1000) < 50);
+        assert!(eased_interpolation(Easing::EaseOut, 10, 90, 500, 1000) > 50);
+        assert!(eased_interpolation(Easing::EaseOut, 90, 10, 500, 1000) < 50);
+        assert_eq!(eased_interpolation(Easing::Sine, 10, 90, 500, 1000), 50);
+
+        // Overshooting bezier curves stay between the two points
+        let back = Easing::CubicBezier {
+            x1: 0.5,
+            y1: -2.0,
+            x2: 0.5,
+            y2: 3.0,
+        };
+        assert_eq!(eased_interpolation(back, 10, 90, 200, 1000), 10);
+        assert_eq!(eased_interpolation(back, 10, 90, 800, 1000), 90);
+        assert_eq!(back, back);
+    }
+
+    #[test]
+    fn curve_options_verification() {
+        assert!(CurveOptions::default().verify().is_ok());
+        assert!(CurveOptions::default()
+            .with_resolution(Duration::from_millis(200))
+            .with_step_gap(Duration::ZERO)
+            .verify()
+            .is_ok());
+        assert!(matches!(
+            CurveOptions::default()
+                .with_resolution(Duration::from_millis(50))
+                .verify(),
+            Err(PiShockError::InvalidCurveOptions(_))
+        ));
+        assert!(matches!(
+            CurveOptions::default().with_start_intensity(101).verify(),
+            Err(PiShockError::InvalidCurveOptions(_))
+        ));
+    }
+
+    #[test]
+    fn plan_curve_statistics() {
+        let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+
+        let plan = pishocker_instance
+            .plan_curve(
+                &[
+                    ShockPoint::new(Duration::from_secs(1), 40).with_easing(Easing::Step),
+                    ShockPoint::new(Duration::from_secs(1), 20).with_easing(Easing::Step),
+                ],
+                CurveOptions::default().with_step_gap(Duration::from_millis(200)),
+            )
+            .unwrap();
+
+        let intensities: Vec<u32> = plan
+            .get_steps()
+            .iter()
+            .map(|step| step.get_intensity())
+            .collect();
+        // Step segments are sent as a single command
+        assert_eq!(intensities, vec![40, 20]);
+        assert_eq!(plan.get_total_duration(), Duration::from_millis(2200));
+        assert_eq!(plan.get_peak_intensity(), 40);
+        assert!((plan.get_average_intensity() - 30.0).abs() < f32::EPSILON);
+        assert_eq!(plan.intensity_at(Duration::from_millis(500)), 40);
+        assert_eq!(plan.intensity_at(Duration::from_millis(1100)), 0);
+        assert_eq!(plan.intensity_at(Duration::from_millis(1500)), 20);
+        assert!(!plan.render_chart(60, 20).is_empty());
+    }
+
+    #[test]
+    fn lazy_steps_match_plan() {
+        let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        let pattern: Pattern = "beep 300ms; vib 10 ramp-> 60 over 3s; wait 1s; shock 20 1s x2"
+            .parse()
+            .unwrap();
+        let options = CurveOptions::default();
+
+        let plan = pishocker_instance.plan_pattern(&pattern, options).unwrap();
+        let mut steps = pishocker_instance.plan_steps(&pattern, options).unwrap();
+
+        // Charting doesn't use up the steps
+        assert!(!steps.render_chart(60, 20).is_empty());
+
+        // A copy continues from the same position
+        let first = steps.next().unwrap();
+        let rest: Vec<_> = steps.clone().collect();
+        assert_eq!(first, plan.get_steps()[0]);
+        assert_eq!(rest, plan.get_steps()[1..]);
+
+        assert_eq!(steps.by_ref().count(), rest.len());
+        assert_eq!(steps.get_elapsed(), plan.get_total_duration());
+    }
+
+    #[test]
+    fn lazy_chart_matches_plan_chart() {
+        let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        let pattern: Pattern = "vib 40 2s; wait 2s; shock 20 1s".parse().unwrap();
+        let options = CurveOptions::default();
+
+        let plan = pishocker_instance.plan_pattern(&pattern, options).unwrap();
+        let steps = pishocker_instance.plan_steps(&pattern, options).unwrap();
+
+        assert_eq!(steps.render_chart(60, 20), plan.render_chart(60, 20));
+    }
+
+    #[test]
+    fn long_ramps_are_computed_on_demand() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut segment = SegmentSteps::new(1, 100, day, Easing::Linear, Duration::ZERO);
+
+        // Resolutions are raised to the shortest command, nothing is computed upfront
+        assert_eq!(segment.size_hint(), (864_000, Some(864_000)));
+        assert_eq!(segment.next(), Some((1, Duration::from_millis(100))));
+        assert_eq!(segment.size_hint().0, 863_999);
+
+        let mut step = SegmentSteps::new(1, 100, day, Easing::Step, Duration::ZERO);
+        assert_eq!(step.size_hint(), (1, Some(1)));
+        assert_eq!(step.next(), Some((100, day)));
+        assert_eq!(step.next(), None);
+    }
+}
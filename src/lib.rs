@@ -1,10 +1,33 @@
 mod api_endpoints;
+pub use self::api_endpoints::PiShockOpCode;
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod budget;
+pub mod consent;
+pub mod emergency;
 pub mod errors;
+pub mod escalation;
+#[cfg(feature = "export")]
+pub mod export;
 mod pishocker;
 pub use self::pishocker::*;
 mod pishock_account;
 pub use self::pishock_account::*;
 pub mod interpolation;
+pub mod limits;
+#[cfg(feature = "midi")]
+pub mod midi;
+pub mod morse;
+pub mod pattern;
+mod pattern_combinators;
+pub mod pattern_dsl;
+pub mod pattern_file;
+pub mod playback;
+pub mod presets;
+pub mod random;
+pub mod safety;
+pub mod validation;
+pub mod warning;
 
 /// The base URL for the PiShock API (without trailing slash)
 static PUBLIC_PISHOCK_API_BASE: &str = "https://do.pishock.com/api";
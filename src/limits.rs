@@ -0,0 +1,338 @@
+use crate::interpolation::Easing;
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::validation::MIN_DURATION;
+use std::borrow::Cow;
+use std::time::Duration;
+
+/// How planning handles steps that exceed the intensity or duration limits of a shocker.
+///
+/// Set it with [`CurveOptions::with_limit_policy`](crate::interpolation::CurveOptions::with_limit_policy),
+/// every change that was made is listed in [`CurvePlan::get_adjustments`](crate::interpolation::CurvePlan::get_adjustments).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum LimitPolicy {
+    /// Fails planning if any step exceeds a limit
+    #[default]
+    Reject,
+    /// Lowers intensities and shortens durations to the limits
+    Clamp,
+    /// Scales all intensities by the same factor so the peak matches the intensity limit, which keeps
+    /// the shape of the pattern. Durations are shortened to the limit.
+    ProportionalRescale,
+    /// Splits commands that are too long into several shorter ones with the same intensity.
+    /// Intensities are lowered to the limit.
+    ///
+    /// The API sends commands of a second or longer in whole seconds, so the parts are whole seconds
+    /// followed by the sub-second rest. A rest shorter than the shortest command can't be sent and is
+    /// dropped, and every part is followed by the usual step gap.
+    SplitLongSegments,
+}
+
+/// A change made by a [`LimitPolicy`], `step` is the index of the affected [`PatternStep`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LimitAdjustment {
+    IntensityClamped {
+        step: usize,
+        original: u32,
+        adjusted: u32,
+    },
+    IntensityRescaled {
+        step: usize,
+        original: u32,
+        adjusted: u32,
+    },
+    DurationClamped {
+        step: usize,
+        original: Duration,
+        adjusted: Duration,
+    },
+    /// The step was sent as `parts` consecutive commands
+    SegmentSplit { step: usize, parts: u32 },
+}
+
+/// Adjusts the intensities and durations of a pattern according to the policy.
+///
+/// The pattern is only copied if the policy can change it. Splitting happens while the steps are
+/// planned, see [`split_adjustments`].
+pub(crate) fn fit_pattern(
+    pattern: Cow<'_, Pattern>,
+    policy: LimitPolicy,
+    max_intensity: Option<u32>,
+    max_duration: Option<Duration>,
+) -> (Cow<'_, Pattern>, Vec<LimitAdjustment>) {
+    let mut adjustments = Vec::new();
+
+    if policy == LimitPolicy::Reject {
+        return (pattern, adjustments);
+    }
+
+    let max_intensity = max_intensity.unwrap_or(100);
+    let peak = pattern
+        .get_steps()
+        .iter()
+        .filter(|step| step.get_op().has_intensity())
+        .map(|step| step.get_intensity().max(step.get_from().unwrap_or(0)))
+        .max()
+        .unwrap_or(0);
+
+    let fit_intensity = |index: usize, intensity: u32, adjustments: &mut Vec<LimitAdjustment>| {
+        if peak <= max_intensity {
+            return intensity;
+        }
+
+        if policy == LimitPolicy::ProportionalRescale {
+            let adjusted = ((intensity as f32 * max_intensity as f32 / peak as f32).round() as u32)
+                .clamp(1, max_intensity);
+            adjustments.push(LimitAdjustment::IntensityRescaled {
+                step: index,
+                original: intensity,
+                adjusted,
+            });
+            adjusted
+        } else if intensity > max_intensity {
+            adjustments.push(LimitAdjustment::IntensityClamped {
+                step: index,
+                original: intensity,
+                adjusted: max_intensity,
+            });
+            max_intensity
+        } else {
+            intensity
+        }
+    };
+
+    let steps = pattern.get_steps().iter().enumerate().map(|(index, step)| {
+        let mut fitted = step.clone();
+
+        if step.get_op().has_intensity() {
+            if let Some(from) = step.get_from() {
+                fitted = fitted.with_from(fit_intensity(index, from, &mut adjustments));
+            }
+            fitted =
+                fitted.with_intensity(fit_intensity(index, step.get_intensity(), &mut adjustments));
+        }
+
+        fitted = fit_duration(index, fitted, policy, max_duration, &mut adjustments);
+        fitted
+    });
+
+    (Cow::Owned(steps.collect()), adjustments)
+}
+
+fn fit_duration(
+    index: usize,
+    step: PatternStep,
+    policy: LimitPolicy,
+    max_duration: Option<Duration>,
+    adjustments: &mut Vec<LimitAdjustment>,
+) -> PatternStep {
+    let Some(max_duration) = max_duration else {
+        return step;
+    };
+
+    // Pauses aren't sent, and long commands are split after interpolation
+    if step.get_op() == PatternOp::Pause
+        || policy == LimitPolicy::SplitLongSegments
+        || step.get_duration() <= max_duration
+    {
+        return step;
+    }
+
+    adjustments.push(LimitAdjustment::DurationClamped {
+        step: index,
+        original: step.get_duration(),
+        adjusted: max_duration,
+    });
+    step.with_duration(max_duration)
+}
+
+/// Returns the next part of a command split to fit `max_duration`, None if the rest is too short to send.
+///
+/// Commands of a second or longer are sent in whole seconds, so parts of that length are cut to whole
+/// seconds and the sub-second rest becomes a part of its own.
+pub(crate) fn next_split_part(remaining: Duration, max_duration: Duration) -> Option<Duration> {
+    let part = remaining.min(max_duration);
+    let part = if part >= Duration::from_secs(1) {
+        Duration::from_secs(part.as_secs())
+    } else {
+        part
+    };
+
+    (part >= MIN_DURATION).then_some(part)
+}
+
+/// Returns the number of parts a command is split into to fit `max_duration`
+pub(crate) fn split_parts(duration: Duration, max_duration: Duration) -> u32 {
+    let Some(chunk) = next_split_part(max_duration, max_duration) else {
+        return 0;
+    };
+
+    // All parts but the last one or two are whole chunks
+    let whole = (duration.as_nanos() / chunk.as_nanos()) as u32;
+    let mut remaining = duration - chunk * whole;
+    let mut parts = whole;
+    while let Some(part) = next_split_part(remaining, max_duration) {
+        remaining -= part;
+        parts += 1;
+    }
+
+    parts
+}
+
+/// Lists the steps that will be split into several commands because they are longer than `max_duration`.
+///
+/// Eased steps are interpolated into pieces of the resolution, which never exceeds the limit, so only
+/// constant commands can be too long.
+pub(crate) fn split_adjustments(
+    pattern: &Pattern,
+    max_duration: Option<Duration>,
+) -> Vec<LimitAdjustment> {
+    let Some(max_duration) = max_duration.filter(|max_duration| !max_duration.is_zero()) else {
+        return Vec::new();
+    };
+
+    pattern
+        .get_steps()
+        .iter()
+        .enumerate()
+        .filter(|(_, step)| {
+            step.get_op() != PatternOp::Pause
+                && (!step.get_op().has_intensity() || step.get_easing() == Easing::Step)
+                && step.get_duration() > max_duration
+        })
+        .map(|(index, step)| LimitAdjustment::SegmentSplit {
+            step: index,
+            parts: split_parts(step.get_duration(), max_duration),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::CurveOptions;
+    use crate::limits::{LimitAdjustment, LimitPolicy};
+    use crate::pattern::{Pattern, PatternOp, PatternStep};
+    use crate::pishocker::PiShockerMetadata;
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    fn capped_shocker() -> PiShocker {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.metadata = Some(PiShockerMetadata {
+            max_intensity: 40,
+            max_duration: 2,
+            online: true,
+            ..Default::default()
+        });
+        pishocker_instance
+    }
+
+    fn pattern() -> Pattern {
+        Pattern::new()
+            .with_step(PatternStep::vibrate(100, Duration::from_secs(1)))
+            .with_step(PatternStep::shock(50, Duration::from_secs(6)))
+    }
+
+    fn planned(pishocker_instance: &PiShocker, policy: LimitPolicy) -> Vec<(PatternOp, u32, u64)> {
+        pishocker_instance
+            .plan_pattern(
+                &pattern(),
+                CurveOptions::default().with_limit_policy(policy),
+            )
+            .unwrap()
+            .get_steps()
+            .iter()
+            .map(|step| {
+                (
+                    step.get_op(),
+                    step.get_intensity(),
+                    step.get_duration().as_millis() as u64,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reject_by_default() {
+        assert!(capped_shocker()
+            .plan_pattern(&pattern(), CurveOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn clamp_and_rescale() {
+        let pishocker_instance = capped_shocker();
+
+        assert_eq!(
+            planned(&pishocker_instance, LimitPolicy::Clamp),
+            vec![(PatternOp::Vibrate, 40, 1000), (PatternOp::Shock, 40, 2000)]
+        );
+        assert_eq!(
+            planned(&pishocker_instance, LimitPolicy::ProportionalRescale),
+            vec![(PatternOp::Vibrate, 40, 1000), (PatternOp::Shock, 20, 2000)]
+        );
+    }
+
+    #[test]
+    fn split_long_segments() {
+        let pishocker_instance = capped_shocker();
+
+        let plan = pishocker_instance
+            .plan_pattern(
+                &pattern(),
+                CurveOptions::default().with_limit_policy(LimitPolicy::SplitLongSegments),
+            )
+            .unwrap();
+
+        assert_eq!(
+            plan.get_adjustments(),
+            &[
+                LimitAdjustment::IntensityClamped {
+                    step: 0,
+                    original: 100,
+                    adjusted: 40
+                },
+                LimitAdjustment::IntensityClamped {
+                    step: 1,
+                    original: 50,
+                    adjusted: 40
+                },
+                LimitAdjustment::SegmentSplit { step: 1, parts: 3 },
+            ]
+        );
+        assert_eq!(
+            planned(&pishocker_instance, LimitPolicy::SplitLongSegments),
+            vec![
+                (PatternOp::Vibrate, 40, 1000),
+                (PatternOp::Shock, 40, 2000),
+                (PatternOp::Shock, 40, 2000),
+                (PatternOp::Shock, 40, 2000),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_into_whole_seconds() {
+        let pishocker_instance = capped_shocker();
+        let split = |duration: Duration| -> Vec<u64> {
+            pishocker_instance
+                .plan_pattern(
+                    &Pattern::new().with_step(PatternStep::shock(40, duration)),
+                    CurveOptions::default().with_limit_policy(LimitPolicy::SplitLongSegments),
+                )
+                .unwrap()
+                .get_steps()
+                .iter()
+                .map(|step| step.get_duration().as_millis() as u64)
+                .collect()
+        };
+
+        assert_eq!(split(Duration::from_millis(5000)), vec![2000, 2000, 1000]);
+        assert_eq!(
+            split(Duration::from_millis(5500)),
+            vec![2000, 2000, 1000, 500]
+        );
+        // The API can't send the last 50ms
+        assert_eq!(split(Duration::from_millis(4050)), vec![2000, 2000]);
+    }
+}
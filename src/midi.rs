@@ -0,0 +1,509 @@
+use crate::errors::PiShockError;
+use crate::interpolation::CurveOptions;
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::validation::MIN_DURATION;
+use crate::PiShocker;
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// The tempo of a MIDI file without tempo events, 120 beats per minute
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// How notes that play at the same time are combined into one intensity
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum Polyphony {
+    /// The loudest note wins
+    #[default]
+    Max,
+    /// The intensities of all notes are added up, capped at 100
+    Sum,
+}
+
+/// Settings for turning MIDI notes into a [`Pattern`] with [`pattern_from_midi`].
+///
+/// Note-on velocity is mapped to intensity and note length to duration. Which operation a note
+/// triggers is looked up by track first, then by channel, then the default operation is used.
+/// By default channel 10 (drums) beeps and every other channel vibrates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiOptions {
+    track_ops: HashMap<usize, PatternOp>,
+    channel_ops: HashMap<u8, PatternOp>,
+    default_op: Option<PatternOp>,
+    polyphony: Polyphony,
+    curve_options: CurveOptions,
+}
+
+impl Default for MidiOptions {
+    fn default() -> Self {
+        Self {
+            track_ops: HashMap::new(),
+            channel_ops: HashMap::from([(10, PatternOp::Beep)]),
+            default_op: Some(PatternOp::Vibrate),
+            polyphony: Polyphony::Max,
+            curve_options: CurveOptions::default(),
+        }
+    }
+}
+
+impl MidiOptions {
+    /// Maps all notes of a track (starting at 0) to an operation, [`PatternOp::Pause`] ignores the track
+    #[must_use]
+    pub fn with_track_op(mut self, track: usize, op: PatternOp) -> Self {
+        self.track_ops.insert(track, op);
+        self
+    }
+
+    /// Maps all notes of a channel (1 to 16, like in most music software) to an operation,
+    /// [`PatternOp::Pause`] ignores the channel
+    #[must_use]
+    pub fn with_channel_op(mut self, channel: u8, op: PatternOp) -> Self {
+        self.channel_ops.insert(channel, op);
+        self
+    }
+
+    /// Sets the operation of notes on unmapped tracks and channels, `None` ignores them (default vibrate)
+    #[must_use]
+    pub fn with_default_op(mut self, default_op: Option<PatternOp>) -> Self {
+        self.default_op = default_op;
+        self
+    }
+
+    #[must_use]
+    pub fn with_polyphony(mut self, polyphony: Polyphony) -> Self {
+        self.polyphony = polyphony;
+        self
+    }
+
+    /// Sets the options the pattern will be played and validated with (default [`CurveOptions::default`]).
+    ///
+    /// Back to back notes are shortened by the step gap so the pattern stays in time with the music.
+    #[must_use]
+    pub fn with_curve_options(mut self, curve_options: CurveOptions) -> Self {
+        self.curve_options = curve_options;
+        self
+    }
+
+    #[must_use]
+    pub fn get_polyphony(&self) -> Polyphony {
+        self.polyphony
+    }
+
+    #[must_use]
+    pub fn get_default_op(&self) -> Option<PatternOp> {
+        self.default_op
+    }
+
+    #[must_use]
+    pub fn get_curve_options(&self) -> CurveOptions {
+        self.curve_options
+    }
+
+    fn op_for(&self, track: usize, channel: u8) -> Option<PatternOp> {
+        self.track_ops
+            .get(&track)
+            .or_else(|| self.channel_ops.get(&(channel + 1)))
+            .copied()
+            .or(self.default_op)
+            .filter(|op| *op != PatternOp::Pause)
+    }
+}
+
+/// A note with its absolute start and end time
+#[derive(Debug, Clone, Copy)]
+struct Note {
+    start: Duration,
+    end: Duration,
+    op: PatternOp,
+    intensity: u32,
+}
+
+/// The operation and intensity sent during a part of the pattern, `None` for silence
+type SegmentCommand = Option<(PatternOp, u32)>;
+
+/// When several operations play at the same time the strongest one is sent
+fn op_priority(op: PatternOp) -> u8 {
+    match op {
+        PatternOp::Pause => 0,
+        PatternOp::Beep => 1,
+        PatternOp::Vibrate => 2,
+        PatternOp::Shock => 3,
+    }
+}
+
+/// Reads a MIDI file and turns its notes into a [`Pattern`].
+///
+/// Notes that play at the same time are flattened: the strongest operation wins (shock over vibrate
+/// over beep), and the intensities of its notes are combined according to the [`Polyphony`].
+/// Notes shorter than 100 milliseconds are lengthened, as the API doesn't accept shorter commands.
+///
+/// # Errors
+/// Returns [`PiShockError::InvalidMidi`] if the file can't be read or parsed.
+pub fn pattern_from_midi<P: AsRef<Path>>(
+    path: P,
+    options: &MidiOptions,
+) -> Result<Pattern, PiShockError> {
+    let data = std::fs::read(path).map_err(|error| PiShockError::InvalidMidi(error.to_string()))?;
+
+    pattern_from_midi_data(&data, options)
+}
+
+/// Like [`pattern_from_midi`], but parses MIDI data that is already in memory
+///
+/// # Errors
+/// Returns [`PiShockError::InvalidMidi`] if the data can't be parsed.
+pub fn pattern_from_midi_data(data: &[u8], options: &MidiOptions) -> Result<Pattern, PiShockError> {
+    let smf = Smf::parse(data).map_err(|error| PiShockError::InvalidMidi(error.to_string()))?;
+
+    Ok(flatten_notes(&read_notes(&smf, options), options))
+}
+
+impl PiShocker {
+    /// Reads a MIDI file and checks the resulting pattern against the limits of this shocker,
+    /// using the [`CurveOptions`] of the [`MidiOptions`].
+    ///
+    /// ```no_run
+    /// # tokio_test::block_on(async {
+    /// use pishock_rs::midi::MidiOptions;
+    /// use pishock_rs::pattern::PatternOp;
+    /// use pishock_rs::PiShockAccount;
+    ///
+    /// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+    /// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+    ///
+    /// let options = MidiOptions::default().with_channel_op(2, PatternOp::Shock);
+    /// let pattern = pishocker_instance.load_midi("level1.mid", &options).expect("Track doesn't fit this shocker");
+    /// pishocker_instance.play_pattern(&pattern, options.get_curve_options()).await.unwrap();
+    /// # })
+    /// ```
+    /// # Errors
+    /// Returns [`PiShockError::InvalidMidi`] if the file can't be read, and the usual intensity
+    /// and duration errors if the pattern exceeds the shocker limits.
+    pub fn load_midi<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &MidiOptions,
+    ) -> Result<Pattern, PiShockError> {
+        let pattern = pattern_from_midi(path, options)?;
+
+        self.plan_pattern(&pattern, options.curve_options)?;
+
+        Ok(pattern)
+    }
+}
+
+/// Converts ticks to wall-clock time, following the tempo changes of all tracks
+struct TempoMap {
+    /// Tick and microseconds per beat of every tempo change, sorted by tick
+    tempos: Vec<(u64, u32)>,
+    timing: Timing,
+}
+
+impl TempoMap {
+    fn new(smf: &Smf) -> Self {
+        let mut tempos: Vec<(u64, u32)> = smf
+            .tracks
+            .iter()
+            .flat_map(|track| {
+                let mut tick = 0;
+                track.iter().filter_map(move |event| {
+                    tick += u64::from(event.delta.as_int());
+                    match event.kind {
+                        TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                            Some((tick, tempo.as_int()))
+                        }
+                        _ => None,
+                    }
+                })
+            })
+            .collect();
+        tempos.sort_by_key(|(tick, _)| *tick);
+
+        Self {
+            tempos,
+            timing: smf.header.timing,
+        }
+    }
+
+    fn time_at(&self, tick: u64) -> Duration {
+        let ticks_per_beat = match self.timing {
+            Timing::Metrical(ticks_per_beat) => f64::from(ticks_per_beat.as_int().max(1)),
+            Timing::Timecode(fps, subframes) => {
+                let ticks_per_second = f64::from(fps.as_f32()) * f64::from(subframes.max(1));
+                return Duration::from_secs_f64(tick as f64 / ticks_per_second);
+            }
+        };
+
+        let mut micros = 0.0;
+        let mut last_tick = 0;
+        let mut tempo = DEFAULT_TEMPO;
+
+        for (change_tick, change_tempo) in &self.tempos {
+            if *change_tick >= tick {
+                break;
+            }
+            micros += (change_tick - last_tick) as f64 * f64::from(tempo) / ticks_per_beat;
+            last_tick = *change_tick;
+            tempo = *change_tempo;
+        }
+        micros += (tick - last_tick) as f64 * f64::from(tempo) / ticks_per_beat;
+
+        Duration::from_secs_f64(micros / 1_000_000.0)
+    }
+}
+
+fn read_notes(smf: &Smf, options: &MidiOptions) -> Vec<Note> {
+    let tempo_map = TempoMap::new(smf);
+    let mut notes = Vec::new();
+
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        // Start tick and velocity of sounding notes by channel and key
+        let mut sounding: HashMap<(u8, u8), Vec<(u64, u8)>> = HashMap::new();
+        let mut tick = 0;
+
+        let mut finish = |channel: u8, start: u64, velocity: u8, end: u64| {
+            if let Some(op) = options.op_for(track_index, channel) {
+                notes.push(Note {
+                    start: tempo_map.time_at(start),
+                    end: tempo_map.time_at(end),
+                    op,
+                    intensity: ((u32::from(velocity) * 100 + 63) / 127).clamp(1, 100),
+                });
+            }
+        };
+
+        for event in track {
+            tick += u64::from(event.delta.as_int());
+
+            let TrackEventKind::Midi { channel, message } = event.kind else {
+                continue;
+            };
+            let channel = channel.as_int();
+
+            match message {
+                MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                    sounding
+                        .entry((channel, key.as_int()))
+                        .or_default()
+                        .push((tick, vel.as_int()));
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    if let Some((start, velocity)) = sounding
+                        .get_mut(&(channel, key.as_int()))
+                        .and_then(Vec::pop)
+                    {
+                        finish(channel, start, velocity, tick);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Notes that are never released end with their track
+        for ((channel, _), starts) in sounding {
+            for (start, velocity) in starts {
+                finish(channel, start, velocity, tick);
+            }
+        }
+    }
+
+    notes
+}
+
+/// Turns overlapping notes into a sequence of single commands and pauses
+fn flatten_notes(notes: &[Note], options: &MidiOptions) -> Pattern {
+    let mut boundaries: Vec<Duration> = notes
+        .iter()
+        .flat_map(|note| [note.start, note.end])
+        .chain([Duration::ZERO])
+        .collect();
+    boundaries.sort();
+    boundaries.dedup();
+
+    // (start, end, op and intensity or None for silence), adjacent equal segments merged
+    let mut segments: Vec<(Duration, Duration, SegmentCommand)> = Vec::new();
+
+    // Sweep over the boundaries, only keeping the notes that are sounding
+    let mut by_start: Vec<&Note> = notes.iter().collect();
+    by_start.sort_by_key(|note| note.start);
+    let mut pending = by_start.into_iter().peekable();
+    let mut active: Vec<&Note> = Vec::new();
+
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        active.retain(|note| start < note.end);
+        while let Some(note) = pending.next_if(|note| note.start <= start) {
+            if start < note.end {
+                active.push(note);
+            }
+        }
+
+        let command = active
+            .iter()
+            .map(|note| note.op)
+            .max_by_key(|op| op_priority(*op))
+            .map(|op| {
+                let intensities = active
+                    .iter()
+                    .filter(|note| note.op == op)
+                    .map(|note| note.intensity);
+                let intensity = match options.polyphony {
+                    Polyphony::Max => intensities.max().unwrap_or(0),
+                    Polyphony::Sum => intensities.sum::<u32>().min(100),
+                };
+                (op, intensity)
+            });
+
+        match segments.last_mut() {
+            Some(last) if last.2 == command => last.1 = end,
+            _ => segments.push((start, end, command)),
+        }
+    }
+
+    // Lengthen short commands without moving anything that comes after them
+    let mut steps: Vec<(SegmentCommand, Duration)> = Vec::new();
+    let mut cursor = Duration::ZERO;
+
+    for (start, end, command) in segments {
+        let start = start.max(cursor);
+        if end <= start {
+            continue;
+        }
+
+        let mut duration = end - start;
+        if command.is_some() {
+            duration = duration.max(MIN_DURATION);
+        }
+
+        steps.push((command, duration));
+        cursor = start + duration;
+    }
+
+    // The planner adds the step gap between back to back commands, make room for it
+    let step_gap = options.curve_options.get_step_gap();
+    for index in 1..steps.len() {
+        let (previous, next) = (steps[index - 1], steps[index]);
+        if previous.0.is_some() && next.0.is_some() && previous.1 >= MIN_DURATION + step_gap {
+            steps[index - 1].1 -= step_gap;
+        }
+    }
+
+    steps
+        .into_iter()
+        .map(|(command, duration)| match command {
+            None => PatternStep::pause(duration),
+            Some((PatternOp::Beep, _)) => PatternStep::beep(duration),
+            Some((op, intensity)) => PatternStep::new(op, intensity, duration),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::midi::{pattern_from_midi_data, MidiOptions, Polyphony};
+    use crate::pattern::{Pattern, PatternOp, PatternStep};
+    use midly::num::{u15, u24, u28, u4, u7};
+    use midly::{
+        Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    };
+    use std::time::Duration;
+
+    /// (delta ticks, channel starting at 0, key, velocity with 0 releasing the note)
+    fn midi(notes: &[(u32, u8, u8, u8)]) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(480)),
+        ));
+
+        // 60 beats per minute, one beat is one second
+        let mut track = vec![TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(1_000_000))),
+        }];
+        track.extend(
+            notes
+                .iter()
+                .map(|(delta, channel, key, velocity)| TrackEvent {
+                    delta: u28::new(*delta),
+                    kind: TrackEventKind::Midi {
+                        channel: u4::new(*channel),
+                        message: MidiMessage::NoteOn {
+                            key: u7::new(*key),
+                            vel: u7::new(*velocity),
+                        },
+                    },
+                }),
+        );
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(track);
+
+        let mut data = Vec::new();
+        smf.write_std(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn notes_to_pattern() {
+        let data = midi(&[
+            // Half a second of silence, then a one second note at full velocity
+            (240, 0, 60, 127),
+            (480, 0, 60, 0),
+            // A drum hit on channel 10 after a one second rest
+            (480, 9, 36, 100),
+            (240, 9, 36, 0),
+        ]);
+
+        assert_eq!(
+            pattern_from_midi_data(&data, &MidiOptions::default()).unwrap(),
+            Pattern::new()
+                .with_step(PatternStep::pause(Duration::from_millis(500)))
+                .with_step(PatternStep::vibrate(100, Duration::from_secs(1)))
+                .with_step(PatternStep::pause(Duration::from_secs(1)))
+                .with_step(PatternStep::beep(Duration::from_millis(500)))
+        );
+    }
+
+    #[test]
+    fn flatten_polyphony() {
+        // Two overlapping notes: 0.0s to 1.0s at 40 and 0.5s to 1.5s at 30
+        let data = midi(&[
+            (0, 0, 60, 51),
+            (240, 0, 64, 38),
+            (240, 0, 60, 0),
+            (240, 0, 64, 0),
+        ]);
+
+        let intensities = |polyphony| -> Vec<(PatternOp, u32, u64)> {
+            pattern_from_midi_data(&data, &MidiOptions::default().with_polyphony(polyphony))
+                .unwrap()
+                .get_steps()
+                .iter()
+                .map(|step| {
+                    (
+                        step.get_op(),
+                        step.get_intensity(),
+                        step.get_duration().as_millis() as u64,
+                    )
+                })
+                .collect()
+        };
+
+        // Back to back commands are shortened by the default 100ms step gap
+        assert_eq!(
+            intensities(Polyphony::Max),
+            vec![(PatternOp::Vibrate, 40, 900), (PatternOp::Vibrate, 30, 500)]
+        );
+        assert_eq!(
+            intensities(Polyphony::Sum),
+            vec![
+                (PatternOp::Vibrate, 40, 400),
+                (PatternOp::Vibrate, 70, 400),
+                (PatternOp::Vibrate, 30, 500),
+            ]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Encodes text as morse code patterns, e.g. to use the shocker as a silent notification channel.
+//!
+//! Timing follows the usual "PARIS" convention: a dot lasts one unit of `1200ms / wpm`, a dash three
+//! units, symbols of a letter are one unit apart, letters three units and words seven units.
+
+use crate::errors::PiShockError;
+use crate::interpolation::CurveOptions;
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::validation::MIN_DURATION;
+use crate::PiShocker;
+use std::time::Duration;
+
+/// The intensity [`PiShocker::send_morse`] vibrates and shocks with
+pub const DEFAULT_MORSE_INTENSITY: u32 = 20;
+
+/// Dots and dashes of all supported characters
+fn code(character: char) -> Option<&'static str> {
+    Some(match character.to_ascii_uppercase() {
+        'A' => ".-",
+        'B' => "-...",
+        'C' => "-.-.",
+        'D' => "-..",
+        'E' => ".",
+        'F' => "..-.",
+        'G' => "--.",
+        'H' => "....",
+        'I' => "..",
+        'J' => ".---",
+        'K' => "-.-",
+        'L' => ".-..",
+        'M' => "--",
+        'N' => "-.",
+        'O' => "---",
+        'P' => ".--.",
+        'Q' => "--.-",
+        'R' => ".-.",
+        'S' => "...",
+        'T' => "-",
+        'U' => "..-",
+        'V' => "...-",
+        'W' => ".--",
+        'X' => "-..-",
+        'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----",
+        '1' => ".----",
+        '2' => "..---",
+        '3' => "...--",
+        '4' => "....-",
+        '5' => ".....",
+        '6' => "-....",
+        '7' => "--...",
+        '8' => "---..",
+        '9' => "----.",
+        '.' => ".-.-.-",
+        ',' => "--..--",
+        '?' => "..--..",
+        '\'' => ".----.",
+        '!' => "-.-.--",
+        '/' => "-..-.",
+        '(' => "-.--.",
+        ')' => "-.--.-",
+        '&' => ".-...",
+        ':' => "---...",
+        ';' => "-.-.-.",
+        '=' => "-...-",
+        '+' => ".-.-.",
+        '-' => "-....-",
+        '"' => ".-..-.",
+        '@' => ".--.-.",
+        _ => return None,
+    })
+}
+
+/// Turns text into a timed [`Pattern`] of dots, dashes and gaps.
+///
+/// ```
+/// # use pishock_rs::morse::MorseEncoder;
+/// # use pishock_rs::pattern::PatternOp;
+/// let pattern = MorseEncoder::new(PatternOp::Beep, 10).encode("SOS").unwrap();
+/// assert_eq!(pattern.to_string(), "beep 120ms; wait 120ms; beep 120ms; wait 120ms; beep 120ms; wait 360ms; beep 360ms; wait 120ms; beep 360ms; wait 120ms; beep 360ms; wait 360ms; beep 120ms; wait 120ms; beep 120ms; wait 120ms; beep 120ms");
+/// ```
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MorseEncoder {
+    op: PatternOp,
+    intensity: u32,
+    wpm: u32,
+}
+
+impl MorseEncoder {
+    /// Creates an encoder for the given operation and speed in words per minute
+    #[must_use]
+    pub fn new(op: PatternOp, wpm: u32) -> Self {
+        Self {
+            op,
+            intensity: DEFAULT_MORSE_INTENSITY,
+            wpm,
+        }
+    }
+
+    /// Sets the intensity of vibrate and shock signals (default 20)
+    #[must_use]
+    pub fn with_intensity(mut self, intensity: u32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> PatternOp {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_wpm(&self) -> u32 {
+        self.wpm
+    }
+
+    /// Returns the fastest speed the device can signal: a dot has to be a valid command and the
+    /// gap between two symbols has to leave room for the firmware delay between commands
+    #[must_use]
+    pub fn max_wpm() -> u32 {
+        let shortest_unit = MIN_DURATION.max(CurveOptions::default().get_step_gap());
+
+        (1200 / shortest_unit.as_millis()) as u32
+    }
+
+    /// Returns the length of a dot
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::MorseTooFast`] if the speed is faster than [`MorseEncoder::max_wpm`].
+    pub fn get_unit(&self) -> Result<Duration, PiShockError> {
+        if self.wpm == 0 || self.wpm > Self::max_wpm() {
+            return Err(PiShockError::MorseTooFast {
+                wpm: self.wpm,
+                max_wpm: Self::max_wpm(),
+            });
+        }
+
+        Ok(Duration::from_millis(1200) / self.wpm)
+    }
+
+    /// Encodes the text, any whitespace separates words
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::MorseTooFast`] if the speed can't be signalled and
+    /// [`PiShockError::UnsupportedMorseCharacter`] for characters without a morse code.
+    pub fn encode(&self, text: &str) -> Result<Pattern, PiShockError> {
+        let unit = self.get_unit()?;
+
+        encode_with_unit(text, unit, |duration| match self.op {
+            PatternOp::Beep => PatternStep::beep(duration),
+            op => PatternStep::new(op, self.intensity, duration),
+        })
+    }
+}
+
+/// Encodes text with a fixed unit length, `signal` creates the step of a dot or dash
+pub(crate) fn encode_with_unit<F: Fn(Duration) -> PatternStep>(
+    text: &str,
+    unit: Duration,
+    signal: F,
+) -> Result<Pattern, PiShockError> {
+    let mut pattern = Pattern::new();
+
+    for (word_index, word) in text.split_whitespace().enumerate() {
+        if word_index > 0 {
+            pattern.push(PatternStep::pause(unit * 7));
+        }
+
+        for (letter_index, character) in word.chars().enumerate() {
+            let code = code(character).ok_or(PiShockError::UnsupportedMorseCharacter(character))?;
+
+            if letter_index > 0 {
+                pattern.push(PatternStep::pause(unit * 3));
+            }
+
+            for (symbol_index, symbol) in code.chars().enumerate() {
+                if symbol_index > 0 {
+                    pattern.push(PatternStep::pause(unit));
+                }
+
+                pattern.push(signal(if symbol == '-' { unit * 3 } else { unit }));
+            }
+        }
+    }
+
+    Ok(pattern)
+}
+
+impl PiShocker {
+    /// Sends text as morse code, vibrations and shocks use [`DEFAULT_MORSE_INTENSITY`].
+    ///
+    /// Use [`MorseEncoder`] with [`PiShocker::play_pattern`] for other intensities.
+    ///
+    /// ```no_run
+    /// # tokio_test::block_on(async {
+    /// use pishock_rs::pattern::PatternOp;
+    /// use pishock_rs::PiShockAccount;
+    ///
+    /// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+    /// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+    ///
+    /// pishocker_instance.send_morse("build ok", PatternOp::Vibrate, 8).await.expect("Failed to send morse code");
+    /// # })
+    /// ```
+    /// # Errors
+    /// Fails before sending anything if the text can't be encoded at this speed, see [`MorseEncoder::encode`],
+    /// otherwise stops at the first failed command.
+    pub async fn send_morse(
+        &self,
+        text: &str,
+        op: PatternOp,
+        wpm: u32,
+    ) -> Result<(), PiShockError> {
+        let pattern = MorseEncoder::new(op, wpm).encode(text)?;
+
+        self.play_pattern(&pattern, CurveOptions::default()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::CurveOptions;
+    use crate::morse::MorseEncoder;
+    use crate::pattern::PatternOp;
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    #[test]
+    fn word_and_letter_gaps() {
+        let pattern = MorseEncoder::new(PatternOp::Vibrate, 12)
+            .with_intensity(30)
+            .encode("et  t")
+            .unwrap();
+
+        assert_eq!(
+            pattern.to_string(),
+            "vib 30 100ms; wait 300ms; vib 30 300ms; wait 700ms; vib 30 300ms"
+        );
+
+        // Pauses cover the gap between commands, so the timing is exact
+        let plan = PiShocker::new("sharecode", "apikey", "username", "pishock_rs")
+            .plan_pattern(&pattern, CurveOptions::default())
+            .unwrap();
+        assert_eq!(plan.get_total_duration(), Duration::from_millis(1700));
+    }
+
+    #[test]
+    fn reject_unsendable_text() {
+        assert!(matches!(
+            MorseEncoder::new(PatternOp::Beep, 13).encode("hi"),
+            Err(PiShockError::MorseTooFast {
+                wpm: 13,
+                max_wpm: 12
+            })
+        ));
+        assert!(matches!(
+            MorseEncoder::new(PatternOp::Beep, 5).encode("ok✓"),
+            Err(PiShockError::UnsupportedMorseCharacter('✓'))
+        ));
+    }
+}
@@ -0,0 +1,529 @@
+use crate::api_endpoints::PiShockOpCode;
+use crate::errors::PiShockError;
+use crate::escalation::{EscalationPolicy, PreviousShock};
+use crate::interpolation::{CurveOptions, CurvePlan, Easing, PlannedSteps, ShockPoint};
+use crate::limits::{fit_pattern, split_adjustments, LimitPolicy};
+use crate::validation::{Command, Violation};
+use crate::warning::WarningPolicy;
+use crate::PiShocker;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::time::Duration;
+
+/// The most steps a pattern written in the text syntax or built with [`Pattern::repeat`] can have
+pub const MAX_PATTERN_STEPS: usize = 100_000;
+
+/// The operation of a single [`PatternStep`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternOp {
+    Beep,
+    Vibrate,
+    Shock,
+    /// Waits without sending anything
+    Pause,
+}
+
+impl PatternOp {
+    /// Returns the API op code, `None` for [`PatternOp::Pause`]
+    #[must_use]
+    pub fn op_code(&self) -> Option<PiShockOpCode> {
+        match self {
+            PatternOp::Beep => Some(PiShockOpCode::Beep),
+            PatternOp::Vibrate => Some(PiShockOpCode::Vibrate),
+            PatternOp::Shock => Some(PiShockOpCode::Shock),
+            PatternOp::Pause => None,
+        }
+    }
+
+    /// Returns whether the intensity of this op means anything
+    #[must_use]
+    pub fn has_intensity(&self) -> bool {
+        matches!(self, PatternOp::Vibrate | PatternOp::Shock)
+    }
+}
+
+impl From<PiShockOpCode> for PatternOp {
+    fn from(op_code: PiShockOpCode) -> Self {
+        match op_code {
+            PiShockOpCode::Beep => PatternOp::Beep,
+            PiShockOpCode::Vibrate => PatternOp::Vibrate,
+            PiShockOpCode::Shock => PatternOp::Shock,
+        }
+    }
+}
+
+/// A single step of a [`Pattern`].
+///
+/// Vibrate and shock steps ease from the intensity of the previous vibrate or shock step (or the one
+/// set with [`PatternStep::with_from`]) to their own intensity. Unlike [`ShockPoint`]s, steps default to [`Easing::Step`], so they are sent as one
+/// constant command unless an easing is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternStep {
+    op: PatternOp,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    intensity: u32,
+    #[serde(rename = "duration_ms", with = "crate::pattern_file::duration_ms")]
+    duration: Duration,
+    #[serde(default = "step_easing", skip_serializing_if = "is_step_easing")]
+    easing: Easing,
+    /// Ramp from this intensity instead of the previous one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from: Option<u32>,
+}
+
+fn is_zero(intensity: &u32) -> bool {
+    *intensity == 0
+}
+
+fn step_easing() -> Easing {
+    Easing::Step
+}
+
+fn is_step_easing(easing: &Easing) -> bool {
+    *easing == Easing::Step
+}
+
+impl PatternStep {
+    #[must_use]
+    pub fn new(op: PatternOp, intensity: u32, duration: Duration) -> Self {
+        Self {
+            op,
+            intensity,
+            duration,
+            easing: step_easing(),
+            from: None,
+        }
+    }
+
+    #[must_use]
+    pub fn beep(duration: Duration) -> Self {
+        Self::new(PatternOp::Beep, 0, duration)
+    }
+
+    #[must_use]
+    pub fn vibrate(intensity: u32, duration: Duration) -> Self {
+        Self::new(PatternOp::Vibrate, intensity, duration)
+    }
+
+    #[must_use]
+    pub fn shock(intensity: u32, duration: Duration) -> Self {
+        Self::new(PatternOp::Shock, intensity, duration)
+    }
+
+    #[must_use]
+    pub fn pause(duration: Duration) -> Self {
+        Self::new(PatternOp::Pause, 0, duration)
+    }
+
+    /// Sets the easing used to reach this step's intensity from the previous one
+    #[must_use]
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    /// Returns a copy of the step with a different intensity
+    #[must_use]
+    pub fn with_intensity(mut self, intensity: u32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Returns a copy of the step with a different duration
+    #[must_use]
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Makes the easing start at the given intensity instead of the intensity of the previous step
+    #[must_use]
+    pub fn with_from(mut self, from: u32) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> PatternOp {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    #[must_use]
+    pub fn get_easing(&self) -> Easing {
+        self.easing
+    }
+
+    #[must_use]
+    pub fn get_from(&self) -> Option<u32> {
+        self.from
+    }
+}
+
+impl From<ShockPoint> for PatternStep {
+    fn from(point: ShockPoint) -> Self {
+        PatternStep::shock(point.get_intensity(), point.get_duration())
+            .with_easing(point.get_easing())
+    }
+}
+
+/// A timeline of beeps, vibrations, shocks and pauses that is played with [`PiShocker::play_pattern`].
+///
+/// ```
+/// # use std::time::Duration;
+/// # use pishock_rs::interpolation::Easing;
+/// # use pishock_rs::pattern::{Pattern, PatternStep};
+/// let pattern = Pattern::new()
+///     .with_step(PatternStep::beep(Duration::from_secs(1)))
+///     .with_step(PatternStep::vibrate(80, Duration::from_secs(3)).with_easing(Easing::EaseIn))
+///     .with_step(PatternStep::pause(Duration::from_millis(500)))
+///     .with_step(PatternStep::shock(40, Duration::from_secs(1)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Pattern {
+    steps: Vec<PatternStep>,
+}
+
+impl Pattern {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a step to the pattern
+    #[must_use]
+    pub fn with_step(mut self, step: PatternStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Appends a step to the pattern
+    pub fn push(&mut self, step: PatternStep) {
+        self.steps.push(step);
+    }
+
+    #[must_use]
+    pub fn get_steps(&self) -> &[PatternStep] {
+        &self.steps
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// A soft vibration warning followed by a shock, this is what [`PiShocker::shock_with_warning`] sends
+    /// unless the shocker has a [`WarningPolicy`].
+    #[must_use]
+    pub fn shock_with_warning(intensity: u32, duration: Duration) -> Self {
+        WarningPolicy::default()
+            .apply(&Pattern::new().with_step(PatternStep::shock(intensity, duration)))
+    }
+}
+
+impl From<Vec<ShockPoint>> for Pattern {
+    fn from(points: Vec<ShockPoint>) -> Self {
+        points.into_iter().map(PatternStep::from).collect()
+    }
+}
+
+impl FromIterator<PatternStep> for Pattern {
+    fn from_iter<T: IntoIterator<Item = PatternStep>>(iter: T) -> Self {
+        Self {
+            steps: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl PiShocker {
+    /// Interpolates a pattern and checks it against the shocker limits without sending anything.
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidCurveOptions`] if the options can't be executed, and the usual
+    /// intensity and duration errors if any step exceeds the shocker limits and the
+    /// [`LimitPolicy`] of the options doesn't fit it.
+    pub fn plan_pattern(
+        &self,
+        pattern: &Pattern,
+        options: CurveOptions,
+    ) -> Result<CurvePlan, PiShockError> {
+        Ok(CurvePlan::new(self.plan_steps(pattern, options)?))
+    }
+
+    /// Checks a pattern like [`PiShocker::plan_pattern`], but computes the interpolated steps on demand
+    /// instead of collecting them, so long patterns only need memory for a single segment.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// use pishock_rs::interpolation::{CurveOptions, Easing};
+    /// use pishock_rs::pattern::{Pattern, PatternStep};
+    /// use pishock_rs::PiShocker;
+    ///
+    /// // An eight hour ramp with a step every 100ms
+    /// let pattern = Pattern::new().with_step(
+    ///     PatternStep::vibrate(60, Duration::from_secs(8 * 60 * 60)).with_easing(Easing::Linear),
+    /// );
+    /// let options = CurveOptions::default().with_resolution(Duration::from_millis(100));
+    ///
+    /// let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+    /// let mut steps = pishocker_instance.plan_steps(&pattern, options).unwrap();
+    /// assert_eq!(steps.next().unwrap().get_intensity(), 1);
+    /// ```
+    /// # Errors
+    /// The same as [`PiShocker::plan_pattern`], all steps are checked before this returns.
+    pub fn plan_steps<'a>(
+        &self,
+        pattern: &'a Pattern,
+        options: CurveOptions,
+    ) -> Result<PlannedSteps<'a>, PiShockError> {
+        options.verify()?;
+
+        if let Some(error) = self.max_duration_error_triggered(options.get_resolution()) {
+            return Err(error);
+        }
+
+        let policy = options.get_limit_policy();
+        let max_intensity = self
+            .get_max_intensity()
+            .map(|max_intensity| max_intensity.clamp(1, 100) as u32);
+        // Warnings are part of the pattern, so they are checked and fitted like any other step
+        let pattern = match self.warning_policy {
+            Some(warning_policy) => warning_policy.warn(Cow::Borrowed(pattern)),
+            None => Cow::Borrowed(pattern),
+        };
+        let (pattern, mut adjustments) =
+            fit_pattern(pattern, policy, max_intensity, self.get_max_duration());
+
+        // Verify that all steps don't exceed duration or intensity limits
+        for step in pattern
+            .steps
+            .iter()
+            .filter(|step| step.op != PatternOp::Pause)
+        {
+            if step.op.has_intensity() {
+                let peak = step.intensity.max(step.from.unwrap_or(0));
+                if let Some(error) = self.max_intensity_error_triggered(peak) {
+                    return Err(error);
+                }
+            }
+
+            // Too long commands are split while planning
+            if policy == LimitPolicy::SplitLongSegments {
+                continue;
+            }
+
+            if let Some(error) = self.max_duration_error_triggered(step.duration) {
+                return Err(error);
+            }
+        }
+
+        let max_command_duration = if policy == LimitPolicy::SplitLongSegments {
+            adjustments.extend(split_adjustments(&pattern, self.get_max_duration()));
+            self.get_max_duration()
+        } else {
+            None
+        };
+
+        let steps = PlannedSteps::new(pattern, options, adjustments, max_command_duration);
+
+        // Only rejected escalations can fail, clamped ones are lowered when sending
+        let escalation_limiter = self.escalation_limiter.filter(|escalation_limiter| {
+            escalation_limiter.get_policy() == EscalationPolicy::Reject
+        });
+        // The plan starts now, after the last shock that was sent. Offsets are relative to the start
+        // of the plan, so the time since that shock is added on top until the plan has a shock of its own.
+        let mut previous_shock: Option<(PreviousShock, Duration, Duration)> = self
+            .last_escalation
+            .lock()
+            .unwrap()
+            .map(|(previous, sent_at)| (previous, Duration::ZERO, sent_at.elapsed()));
+
+        // Run the same checks as the API request on a copy, the shocker state is checked when sending
+        for step in steps.clone() {
+            let Some(op_code) = step.get_op().op_code() else {
+                continue;
+            };
+
+            if let (Some(escalation_limiter), PatternOp::Shock) =
+                (escalation_limiter, step.get_op())
+            {
+                escalation_limiter.limit(
+                    previous_shock.as_ref().map(|(previous, _, _)| previous),
+                    previous_shock
+                        .as_ref()
+                        .map_or(Duration::ZERO, |(_, offset, before_start)| {
+                            step.get_offset()
+                                .saturating_sub(*offset)
+                                .saturating_add(*before_start)
+                        }),
+                    step.get_intensity(),
+                )?;
+                previous_shock = Some((
+                    PreviousShock::new(step.get_intensity(), step.get_duration()),
+                    step.get_offset(),
+                    Duration::ZERO,
+                ));
+            }
+
+            let violation = self
+                .validate_without_consent(&Command::new(
+                    op_code,
+                    step.get_intensity(),
+                    step.get_duration(),
+                ))
+                .into_iter()
+                .find(|violation| {
+                    matches!(
+                        violation,
+                        Violation::DurationTooLong { .. }
+                            | Violation::IntensityTooHigh { .. }
+                            | Violation::IntensityTooLow
+                            | Violation::DurationTooShort
+                            | Violation::SafetyPolicy(_)
+                    )
+                });
+
+            if let Some(violation) = violation {
+                return Err(self.violation_to_error(violation));
+            }
+        }
+
+        Ok(steps)
+    }
+
+    /// Plays a [`Pattern`], see [`PiShocker::plan_pattern`] to preview it first.
+    ///
+    /// ```no_run
+    /// # tokio_test::block_on(async {
+    /// use std::time::Duration;
+    /// use pishock_rs::interpolation::CurveOptions;
+    /// use pishock_rs::pattern::{Pattern, PatternStep};
+    /// use pishock_rs::PiShockAccount;
+    ///
+    /// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+    /// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+    ///
+    /// let pattern = Pattern::new()
+    ///     .with_step(PatternStep::beep(Duration::from_secs(1)))
+    ///     .with_step(PatternStep::vibrate(50, Duration::from_secs(2)));
+    ///
+    /// pishocker_instance.play_pattern(&pattern, CurveOptions::default()).await.expect("Failed to play pattern");
+    /// # })
+    /// ```
+    /// # Errors
+    /// Fails before sending anything if any step exceeds the shocker limits, otherwise stops at the first failed command.
+    pub async fn play_pattern(
+        &self,
+        pattern: &Pattern,
+        options: CurveOptions,
+    ) -> Result<(), PiShockError> {
+        let steps = self.plan_steps(pattern, options)?;
+
+        self.play_steps(steps).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::{CurveOptions, Easing};
+    use crate::pattern::{Pattern, PatternOp, PatternStep};
+    use crate::PiShockAccount;
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn plan_mixed_pattern() {
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+
+        let pattern = Pattern::new()
+            .with_step(PatternStep::beep(Duration::from_secs(1)))
+            .with_step(PatternStep::vibrate(40, Duration::from_secs(1)).with_easing(Easing::Linear))
+            .with_step(PatternStep::pause(Duration::from_millis(50)))
+            .with_step(PatternStep::shock(30, Duration::from_secs(2)));
+
+        let plan = pishocker_instance
+            .plan_pattern(&pattern, CurveOptions::default())
+            .unwrap();
+
+        let steps: Vec<(PatternOp, u32, u64)> = plan
+            .get_steps()
+            .iter()
+            .map(|step| {
+                (
+                    step.get_op(),
+                    step.get_intensity(),
+                    step.get_offset().as_millis() as u64,
+                )
+            })
+            .collect();
+
+        // The pause is shorter than the step gap, so the gap is only topped up to 100ms
+        assert_eq!(
+            steps,
+            vec![
+                (PatternOp::Beep, 0, 0),
+                (PatternOp::Vibrate, 1, 1100),
+                (PatternOp::Vibrate, 20, 1700),
+                (PatternOp::Pause, 0, 2200),
+                (PatternOp::Shock, 30, 2300),
+            ]
+        );
+        assert_eq!(plan.get_total_duration(), Duration::from_millis(4300));
+    }
+
+    #[test(tokio::test)]
+    async fn play_shock_with_warning() {
+        let mockserver = MockServer::start();
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+
+        // Get a PiShocker instance without verification (we can't set the API server URL to the mock server URL yet)
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        let mocks = [(1, 20, 1), (0, 50, 2)].map(|(op, intensity, duration)| {
+            mockserver.mock(|when, then| {
+                when.method(POST).path("/apioperate/").json_body(json!({
+                    "Op": op,
+                    "Intensity": intensity,
+                    "Duration": duration,
+                    "Code": "sharecode",
+                    "Apikey": "apikey",
+                    "Name": "pishock_rs",
+                    "Username": "username"
+                }));
+                then.status(200).body("Operation Succeeded.");
+            })
+        });
+
+        pishocker_instance
+            .shock_with_warning(50, Duration::from_secs(2))
+            .await
+            .unwrap();
+
+        for mock in mocks {
+            mock.assert();
+        }
+    }
+}
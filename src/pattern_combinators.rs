@@ -0,0 +1,308 @@
+use crate::interpolation::{Easing, INTERPOLATION_DEFAULT_START_Y};
+use crate::pattern::{Pattern, PatternStep, MAX_PATTERN_STEPS};
+use crate::validation::MIN_DURATION;
+use crate::PiShocker;
+use std::time::Duration;
+
+/// Pure transformations that build bigger patterns out of small ones.
+///
+/// None of these send anything, preview the result with [`PiShocker::plan_pattern`].
+/// Durations refer to the steps only, the gaps between commands are added when planning.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use pishock_rs::pattern::{Pattern, PatternStep};
+/// let pulse = Pattern::new()
+///     .with_step(PatternStep::vibrate(40, Duration::from_millis(500)))
+///     .with_step(PatternStep::pause(Duration::from_millis(500)));
+///
+/// let pattern = pulse.repeat(3).concat(&pulse.scale_intensity(2.0).time_stretch(0.5));
+/// assert_eq!(pattern.get_total_duration(), Duration::from_millis(3500));
+/// ```
+impl Pattern {
+    /// Returns the sum of all step durations, saturating at [`Duration::MAX`]
+    #[must_use]
+    pub fn get_total_duration(&self) -> Duration {
+        self.get_steps()
+            .iter()
+            .map(PatternStep::get_duration)
+            .fold(Duration::ZERO, Duration::saturating_add)
+    }
+
+    /// Appends the steps of another pattern
+    #[must_use]
+    pub fn concat(&self, other: &Pattern) -> Pattern {
+        self.get_steps()
+            .iter()
+            .chain(other.get_steps())
+            .cloned()
+            .collect()
+    }
+
+    /// Plays the pattern `count` times in a row
+    ///
+    /// The count is capped so the result has at most [`MAX_PATTERN_STEPS`] steps.
+    #[must_use]
+    pub fn repeat(&self, count: usize) -> Pattern {
+        let steps = self.get_steps();
+        let count = count.min(MAX_PATTERN_STEPS / steps.len().max(1));
+
+        steps
+            .iter()
+            .cycle()
+            .take(steps.len() * count)
+            .cloned()
+            .collect()
+    }
+
+    /// Repeats the pattern until it lasts exactly `duration`, the last step is cut short if needed
+    #[must_use]
+    pub fn loop_for(&self, duration: Duration) -> Pattern {
+        let mut pattern = Pattern::new();
+        if self.get_total_duration().is_zero() {
+            return pattern;
+        }
+
+        let mut remaining = duration;
+        for step in self.get_steps().iter().cycle() {
+            if remaining.is_zero() {
+                break;
+            }
+
+            let step_duration = step.get_duration().min(remaining);
+            pattern.push(step.clone().with_duration(step_duration));
+            remaining -= step_duration;
+        }
+
+        pattern
+    }
+
+    /// Plays the pattern backwards, ramps run from their end intensity to their start intensity
+    ///
+    /// The first ramp of a pattern without a `from` intensity is assumed to start at 1, like curves do by default.
+    #[must_use]
+    pub fn reverse(&self) -> Pattern {
+        let mut previous_intensity = INTERPOLATION_DEFAULT_START_Y;
+        let mut reversed = Vec::with_capacity(self.get_steps().len());
+
+        for step in self.get_steps() {
+            if !step.get_op().has_intensity() || step.get_easing() == Easing::Step {
+                if step.get_op().has_intensity() {
+                    previous_intensity = step.get_intensity();
+                }
+                reversed.push(step.clone());
+                continue;
+            }
+
+            let start = step.get_from().unwrap_or(previous_intensity);
+            previous_intensity = step.get_intensity();
+
+            reversed.push(
+                PatternStep::new(step.get_op(), start, step.get_duration())
+                    .with_easing(mirror_easing(step.get_easing()))
+                    .with_from(step.get_intensity()),
+            );
+        }
+
+        reversed.into_iter().rev().collect()
+    }
+
+    /// Multiplies all vibrate and shock intensities, the result is kept between 1 and 100
+    #[must_use]
+    pub fn scale_intensity(&self, factor: f32) -> Pattern {
+        self.map_intensities(|intensity| (intensity as f32 * factor).round() as i64)
+    }
+
+    /// Adds to all vibrate and shock intensities, the result is kept between 1 and 100
+    #[must_use]
+    pub fn offset_intensity(&self, offset: i32) -> Pattern {
+        self.map_intensities(|intensity| i64::from(intensity) + i64::from(offset))
+    }
+
+    /// Multiplies all step durations, values below 1.0 speed the pattern up
+    ///
+    /// Durations too long to represent saturate at [`Duration::MAX`].
+    #[must_use]
+    pub fn time_stretch(&self, factor: f32) -> Pattern {
+        self.get_steps()
+            .iter()
+            .map(|step| {
+                let duration = Duration::try_from_secs_f32(
+                    step.get_duration().as_secs_f32() * factor.max(0.0),
+                )
+                .unwrap_or(Duration::MAX);
+                step.clone().with_duration(duration)
+            })
+            .collect()
+    }
+
+    /// Clamps all steps to the limits of the shocker and the API, without dropping any step
+    ///
+    /// Shockers without metadata are only clamped to the API limits (intensity 1 to 100, at least 100ms per command).
+    #[must_use]
+    pub fn clamp_to(&self, shocker: &PiShocker) -> Pattern {
+        let max_intensity = shocker
+            .get_max_intensity()
+            .map_or(100, |max_intensity| max_intensity.clamp(1, 100) as u32);
+        let max_duration = shocker.get_max_duration().unwrap_or(Duration::MAX);
+
+        self.clamp_to_limits(max_intensity, max_duration)
+    }
+
+    pub(crate) fn clamp_to_limits(&self, max_intensity: u32, max_duration: Duration) -> Pattern {
+        self.map_intensities(|intensity| i64::from(intensity.min(max_intensity)))
+            .get_steps()
+            .iter()
+            .map(|step| {
+                if step.get_op().op_code().is_none() {
+                    return step.clone();
+                }
+
+                let duration = step
+                    .get_duration()
+                    .clamp(MIN_DURATION, max_duration.max(MIN_DURATION));
+                step.clone().with_duration(duration)
+            })
+            .collect()
+    }
+
+    fn map_intensities<F: Fn(u32) -> i64>(&self, map: F) -> Pattern {
+        let clamp = |intensity: u32| map(intensity).clamp(1, 100) as u32;
+
+        self.get_steps()
+            .iter()
+            .map(|step| {
+                if !step.get_op().has_intensity() {
+                    return step.clone();
+                }
+
+                let mut mapped = step.clone().with_intensity(clamp(step.get_intensity()));
+                if let Some(from) = step.get_from() {
+                    mapped = mapped.with_from(clamp(from));
+                }
+                mapped
+            })
+            .collect()
+    }
+}
+
+/// Returns the easing that traces the same shape when played backwards
+fn mirror_easing(easing: Easing) -> Easing {
+    match easing {
+        Easing::EaseIn => Easing::EaseOut,
+        Easing::EaseOut => Easing::EaseIn,
+        Easing::CubicBezier { x1, y1, x2, y2 } => Easing::CubicBezier {
+            x1: 1.0 - x2,
+            y1: 1.0 - y2,
+            x2: 1.0 - x1,
+            y2: 1.0 - y1,
+        },
+        symmetric => symmetric,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::Easing;
+    use crate::pattern::{Pattern, MAX_PATTERN_STEPS};
+    use crate::pishocker::PiShockerMetadata;
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    fn pattern(dsl: &str) -> Pattern {
+        dsl.parse().unwrap()
+    }
+
+    #[test]
+    fn repeat_and_loop() {
+        let pulse = pattern("vib 40 1s; wait 500ms");
+
+        assert_eq!(
+            pulse.repeat(2),
+            pattern("vib 40 1s; wait 500ms; vib 40 1s; wait 500ms")
+        );
+        assert_eq!(pulse.repeat(0), Pattern::new());
+        assert_eq!(
+            pulse.loop_for(Duration::from_millis(2200)),
+            pattern("vib 40 1s; wait 500ms; vib 40 700ms")
+        );
+        assert_eq!(
+            Pattern::new().loop_for(Duration::from_secs(1)),
+            Pattern::new()
+        );
+    }
+
+    #[test]
+    fn huge_repeat_counts_are_capped() {
+        let pulse = pattern("vib 40 1s; wait 500ms; beep 1s");
+
+        assert_eq!(
+            pulse.repeat(usize::MAX).get_steps().len(),
+            MAX_PATTERN_STEPS / 3 * 3
+        );
+        assert_eq!(Pattern::new().repeat(usize::MAX), Pattern::new());
+    }
+
+    #[test]
+    fn reverse_ramps() {
+        let swell = pattern("vib 10 1s; vib ramp-> 60 over 2s ease-in; shock 30 1s");
+
+        assert_eq!(
+            swell.reverse(),
+            pattern("shock 30 1s; vib 60 ramp-> 10 over 2s ease-out; vib 10 1s")
+        );
+        assert_eq!(
+            swell.reverse().reverse().get_steps()[1].get_easing(),
+            Easing::EaseIn
+        );
+    }
+
+    #[test]
+    fn intensity_and_time_transformations() {
+        let ramp = pattern("vib 20 ramp-> 80 over 2s; beep 1s");
+
+        assert_eq!(
+            ramp.scale_intensity(2.0),
+            pattern("vib 40 ramp-> 100 over 2s; beep 1s")
+        );
+        assert_eq!(
+            ramp.offset_intensity(-30),
+            pattern("vib 1 ramp-> 50 over 2s; beep 1s")
+        );
+        assert_eq!(
+            ramp.time_stretch(0.5),
+            pattern("vib 20 ramp-> 80 over 1s; beep 500ms")
+        );
+    }
+
+    #[test]
+    fn huge_time_stretch_saturates() {
+        let ramp = pattern("vib 20 ramp-> 80 over 2s; beep 1s");
+
+        for factor in [1e30, f32::INFINITY] {
+            let stretched = ramp.time_stretch(factor);
+            assert!(stretched
+                .get_steps()
+                .iter()
+                .all(|step| step.get_duration() == Duration::MAX));
+            assert_eq!(stretched.get_total_duration(), Duration::MAX);
+        }
+    }
+
+    #[test]
+    fn clamp_to_shocker_limits() {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.metadata = Some(PiShockerMetadata {
+            max_intensity: 40,
+            max_duration: 2,
+            online: true,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            pattern("shock 80 5s; wait 10s; vib 20 50ms").clamp_to(&pishocker_instance),
+            pattern("shock 40 2s; wait 10s; vib 20 100ms")
+        );
+    }
+}
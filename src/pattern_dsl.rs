@@ -0,0 +1,534 @@
+//! A compact text syntax for [`Pattern`]s, meant for chat commands and config files.
+//!
+//! Statements are separated by `;` or new lines, `#` starts a comment:
+//!
+//! ```text
+//! beep 1s                                # beep for one second
+//! vib 30 ramp-> 80 over 3s               # vibrate, ramping from 30 to 80
+//! wait 500ms                             # pause
+//! shock ramp-> 40 over 2s ease-in        # ramp from the previous intensity
+//! shock 40 2s x3                         # repeat the statement three times
+//! vib 20 ramp-> 60 over 1.5s bezier(0.4,0,0.2,1)
+//! ```
+//!
+//! A statement can be repeated at most [`MAX_REPEAT`] times, and a pattern can have at most
+//! [`MAX_PATTERN_STEPS`] steps.
+//!
+//! Durations take `ms`, `s` or `m` units. Ramps are linear unless one of `linear`, `ease-in`,
+//! `ease-out`, `ease-in-out`, `sine` or `bezier(x1,y1,x2,y2)` follows the duration.
+//!
+//! ```
+//! # use pishock_rs::pattern::Pattern;
+//! let pattern: Pattern = "beep 1s; vib 30 ramp-> 80 over 3s; wait 500ms; shock 40 2s x3"
+//!     .parse()
+//!     .unwrap();
+//!
+//! assert_eq!(pattern.get_steps().len(), 6);
+//! assert_eq!(
+//!     pattern.to_string(),
+//!     "beep 1s; vib 30 ramp-> 80 over 3s; wait 500ms; shock 40 2s x3"
+//! );
+//! ```
+
+use crate::errors::PiShockError;
+use crate::interpolation::Easing;
+use crate::pattern::{Pattern, PatternOp, PatternStep, MAX_PATTERN_STEPS};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The highest repeat count a statement can have
+pub const MAX_REPEAT: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Separator,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error<S: Into<String>>(&self, message: S) -> PiShockError {
+        PiShockError::InvalidPatternSyntax {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    fn text(&self) -> &str {
+        match &self.kind {
+            TokenKind::Word(word) => word,
+            TokenKind::Separator => ";",
+        }
+    }
+}
+
+/// Splits the input into words and statement separators, keeping track of their positions
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(&character) = chars.peek() {
+        match character {
+            '\n' | ';' => {
+                tokens.push(Token {
+                    kind: TokenKind::Separator,
+                    line,
+                    column,
+                });
+                chars.next();
+                if character == '\n' {
+                    line += 1;
+                    column = 1;
+                } else {
+                    column += 1;
+                }
+            }
+            '#' => {
+                // Comments run until the end of the line, the new line itself is still a separator
+                while chars.peek().is_some_and(|&character| character != '\n') {
+                    chars.next();
+                    column += 1;
+                }
+            }
+            character if character.is_whitespace() => {
+                chars.next();
+                column += 1;
+            }
+            _ => {
+                let (start_line, start_column) = (line, column);
+                let mut word = String::new();
+                let mut in_parentheses = false;
+
+                while let Some(&character) = chars.peek() {
+                    let ends_word = character == '\n'
+                        || (!in_parentheses
+                            && (character.is_whitespace() || character == ';' || character == '#'));
+                    if ends_word {
+                        break;
+                    }
+
+                    match character {
+                        '(' => in_parentheses = true,
+                        ')' => in_parentheses = false,
+                        _ => {}
+                    }
+
+                    // Whitespace inside of parentheses is dropped, `bezier(0.4, 0, 0.2, 1)` is one word
+                    if !character.is_whitespace() {
+                        word.push(character);
+                    }
+                    chars.next();
+                    column += 1;
+                }
+
+                tokens.push(Token {
+                    kind: TokenKind::Word(word),
+                    line: start_line,
+                    column: start_column,
+                });
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Parses a pattern from the text syntax described in the [module documentation](self)
+///
+/// # Errors
+/// Returns [`PiShockError::InvalidPatternSyntax`] with the line and column of the offending word,
+/// also if a repeat count is above [`MAX_REPEAT`] or the pattern would get more than [`MAX_PATTERN_STEPS`] steps.
+pub fn parse(input: &str) -> Result<Pattern, PiShockError> {
+    let tokens = tokenize(input);
+    let mut pattern = Pattern::new();
+
+    for statement in tokens.split(|token| token.kind == TokenKind::Separator) {
+        if statement.is_empty() {
+            continue;
+        }
+
+        let (step, repeat) = parse_statement(statement, pattern.get_steps().len())?;
+        for _ in 0..repeat {
+            pattern.push(step.clone());
+        }
+    }
+
+    Ok(pattern)
+}
+
+fn parse_statement(
+    statement: &[Token],
+    steps_before: usize,
+) -> Result<(PatternStep, usize), PiShockError> {
+    let mut words = statement.iter().peekable();
+    // The caller never passes empty statements
+    let keyword = words.next().unwrap();
+
+    let op = match keyword.text() {
+        "beep" => PatternOp::Beep,
+        "vib" | "vibrate" => PatternOp::Vibrate,
+        "shock" => PatternOp::Shock,
+        "wait" | "pause" => PatternOp::Pause,
+        other => {
+            return Err(keyword.error(format!(
+                "unknown operation `{other}`, expected beep, vib, shock or wait"
+            )))
+        }
+    };
+
+    let expect = |token: Option<&Token>, what: &str| -> Result<Token, PiShockError> {
+        token.cloned().ok_or_else(|| {
+            let last = statement.last().unwrap();
+            PiShockError::InvalidPatternSyntax {
+                line: last.line,
+                column: last.column + last.text().chars().count(),
+                message: format!("expected {what}"),
+            }
+        })
+    };
+
+    let step = if op.has_intensity() {
+        let first = expect(words.next(), "an intensity or `ramp->`")?;
+
+        let from = if first.text() == "ramp->" {
+            None
+        } else {
+            Some(parse_intensity(&first)?)
+        };
+
+        let ramp_follows = words.peek().is_some_and(|token| token.text() == "ramp->");
+
+        match from {
+            Some(intensity) if !ramp_follows => {
+                let duration = parse_duration(&expect(words.next(), "a duration")?)?;
+                PatternStep::new(op, intensity, duration)
+            }
+            from => {
+                if from.is_some() {
+                    words.next();
+                }
+
+                let to = parse_intensity(&expect(words.next(), "a target intensity")?)?;
+                let over = expect(words.next(), "`over`")?;
+                if over.text() != "over" {
+                    return Err(over.error(format!("expected `over`, got `{}`", over.text())));
+                }
+                let duration = parse_duration(&expect(words.next(), "a duration")?)?;
+
+                let easing = match words.peek() {
+                    Some(token) if !is_repeat(token) => parse_easing(words.next().unwrap())?,
+                    _ => Easing::Linear,
+                };
+
+                let step = PatternStep::new(op, to, duration).with_easing(easing);
+                match from {
+                    Some(from) => step.with_from(from),
+                    None => step,
+                }
+            }
+        }
+    } else {
+        let duration = parse_duration(&expect(words.next(), "a duration")?)?;
+        PatternStep::new(op, 0, duration)
+    };
+
+    // Errors about the number of steps point at the repeat count, or at the keyword without one
+    let (repeat, repeat_token) = match words.next() {
+        Some(token) if is_repeat(token) => (parse_repeat(token)?, token),
+        Some(token) => {
+            return Err(token.error(format!("unexpected `{}`", token.text())));
+        }
+        None => (1, keyword),
+    };
+
+    if let Some(token) = words.next() {
+        return Err(token.error(format!("unexpected `{}`", token.text())));
+    }
+
+    if steps_before + repeat > MAX_PATTERN_STEPS {
+        return Err(repeat_token.error(format!(
+            "the pattern would have more than {MAX_PATTERN_STEPS} steps"
+        )));
+    }
+
+    Ok((step, repeat))
+}
+
+fn is_repeat(token: &Token) -> bool {
+    let text = token.text();
+    text.len() > 1 && text.starts_with('x') && text[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_repeat(token: &Token) -> Result<usize, PiShockError> {
+    let repeat = token.text()[1..]
+        .parse::<usize>()
+        .ok()
+        .filter(|repeat| *repeat > 0)
+        .ok_or_else(|| token.error(format!("invalid repeat count `{}`", token.text())))?;
+
+    if repeat > MAX_REPEAT {
+        return Err(token.error(format!(
+            "repeat count {repeat} is above the maximum of {MAX_REPEAT}"
+        )));
+    }
+
+    Ok(repeat)
+}
+
+fn parse_intensity(token: &Token) -> Result<u32, PiShockError> {
+    token
+        .text()
+        .parse::<u32>()
+        .ok()
+        .filter(|intensity| *intensity <= 100)
+        .ok_or_else(|| {
+            token.error(format!(
+                "invalid intensity `{}`, expected a number between 0 and 100",
+                token.text()
+            ))
+        })
+}
+
+fn parse_duration(token: &Token) -> Result<Duration, PiShockError> {
+    let text = token.text();
+    let (number, milliseconds_per_unit) = if let Some(number) = text.strip_suffix("ms") {
+        (number, 1.0)
+    } else if let Some(number) = text.strip_suffix('s') {
+        (number, 1000.0)
+    } else if let Some(number) = text.strip_suffix('m') {
+        (number, 60_000.0)
+    } else {
+        return Err(token.error(format!(
+            "invalid duration `{text}`, expected a unit like 500ms, 2s or 1m"
+        )));
+    };
+
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .map(|number| Duration::from_millis((number * milliseconds_per_unit).round() as u64))
+        .ok_or_else(|| token.error(format!("invalid duration `{text}`")))
+}
+
+fn parse_easing(token: &Token) -> Result<Easing, PiShockError> {
+    let text = token.text();
+    match text {
+        "linear" => return Ok(Easing::Linear),
+        "ease-in" => return Ok(Easing::EaseIn),
+        "ease-out" => return Ok(Easing::EaseOut),
+        "ease-in-out" => return Ok(Easing::EaseInOut),
+        "sine" => return Ok(Easing::Sine),
+        _ => {}
+    }
+
+    let control_points: Option<Vec<f32>> = text
+        .strip_prefix("bezier(")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .and_then(|arguments| {
+            arguments
+                .split(',')
+                .map(|number| number.parse::<f32>().ok())
+                .collect()
+        });
+
+    match control_points.as_deref() {
+        Some(&[x1, y1, x2, y2]) => Ok(Easing::CubicBezier { x1, y1, x2, y2 }),
+        _ => Err(token.error(format!(
+            "unknown easing `{text}`, expected linear, ease-in, ease-out, ease-in-out, sine or bezier(x1,y1,x2,y2)"
+        ))),
+    }
+}
+
+struct DurationDisplay(Duration);
+
+impl fmt::Display for DurationDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let milliseconds = self.0.as_millis();
+        if milliseconds > 0 && milliseconds.is_multiple_of(1000) {
+            write!(f, "{}s", milliseconds / 1000)
+        } else {
+            write!(f, "{milliseconds}ms")
+        }
+    }
+}
+
+impl fmt::Display for PatternStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keyword = match self.get_op() {
+            PatternOp::Beep => "beep",
+            PatternOp::Vibrate => "vib",
+            PatternOp::Shock => "shock",
+            PatternOp::Pause => "wait",
+        };
+        let duration = DurationDisplay(self.get_duration());
+
+        if !self.get_op().has_intensity() {
+            return write!(f, "{keyword} {duration}");
+        }
+
+        if self.get_easing() == Easing::Step {
+            return write!(f, "{keyword} {} {duration}", self.get_intensity());
+        }
+
+        write!(f, "{keyword} ")?;
+        if let Some(from) = self.get_from() {
+            write!(f, "{from} ")?;
+        }
+        write!(f, "ramp-> {} over {duration}", self.get_intensity())?;
+
+        match self.get_easing() {
+            Easing::Step | Easing::Linear => Ok(()),
+            Easing::EaseIn => write!(f, " ease-in"),
+            Easing::EaseOut => write!(f, " ease-out"),
+            Easing::EaseInOut => write!(f, " ease-in-out"),
+            Easing::Sine => write!(f, " sine"),
+            Easing::CubicBezier { x1, y1, x2, y2 } => write!(f, " bezier({x1},{y1},{x2},{y2})"),
+        }
+    }
+}
+
+/// Prints the pattern in the text syntax, repeated steps are collapsed with `xN` up to [`MAX_REPEAT`]
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps = self.get_steps();
+        let mut index = 0;
+
+        while index < steps.len() {
+            let repeat = steps[index..]
+                .iter()
+                .take_while(|step| **step == steps[index])
+                .take(MAX_REPEAT)
+                .count();
+
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", steps[index])?;
+            if repeat > 1 {
+                write!(f, " x{repeat}")?;
+            }
+
+            index += repeat;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = PiShockError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        parse(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::Easing;
+    use crate::pattern::{Pattern, PatternStep, MAX_PATTERN_STEPS};
+    use crate::pattern_dsl::{parse, MAX_REPEAT};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_example() {
+        let pattern =
+            parse("beep 1s; vib 30 ramp-> 80 over 3s; wait 500ms; shock 40 2s x3").unwrap();
+
+        let shock = PatternStep::shock(40, Duration::from_secs(2));
+        assert_eq!(
+            pattern,
+            Pattern::new()
+                .with_step(PatternStep::beep(Duration::from_secs(1)))
+                .with_step(
+                    PatternStep::vibrate(80, Duration::from_secs(3))
+                        .with_easing(Easing::Linear)
+                        .with_from(30)
+                )
+                .with_step(PatternStep::pause(Duration::from_millis(500)))
+                .with_step(shock.clone())
+                .with_step(shock.clone())
+                .with_step(shock)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = "vib ramp-> 60 over 1500ms bezier(0.4,0,0.2,1); shock 10 ramp-> 30 over 2s ease-out x2; pause 1m; beep 100ms";
+        let pattern = parse(input).unwrap();
+
+        assert_eq!(parse(&pattern.to_string()).unwrap(), pattern);
+        assert_eq!(
+            pattern.to_string(),
+            "vib ramp-> 60 over 1500ms bezier(0.4,0,0.2,1); shock 10 ramp-> 30 over 2s ease-out x2; wait 60s; beep 100ms"
+        );
+    }
+
+    #[test]
+    fn multiline_with_comments() {
+        let pattern = parse("# warm up\nvibrate 20 1s\n\n  shock 30 500ms # ouch\n").unwrap();
+
+        assert_eq!(pattern.get_steps().len(), 2);
+    }
+
+    #[test]
+    fn error_positions() {
+        let expectations = [
+            ("beep 1s; zap 20 1s", 1, 10),
+            ("beep 1s\nvib 200 1s", 2, 5),
+            ("vib 20 ramp-> 40 2s", 1, 18),
+            ("shock 20 2h", 1, 10),
+            ("vib 20", 1, 7),
+            ("wait 1s x0", 1, 9),
+        ];
+
+        for (input, expected_line, expected_column) in expectations {
+            match parse(input) {
+                Err(PiShockError::InvalidPatternSyntax { line, column, .. }) => {
+                    assert_eq!((line, column), (expected_line, expected_column), "{input}");
+                }
+                other => panic!("Expected syntax error for `{input}`, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn repeat_limits() {
+        let too_many_repeats = format!("wait 1s; beep 1s x{}", MAX_REPEAT + 1);
+        let too_many_steps = format!("beep 1s x{MAX_REPEAT}\n")
+            .repeat(MAX_PATTERN_STEPS / MAX_REPEAT)
+            + "vib 20 1s x1";
+
+        for (input, expected_line, expected_column) in [
+            ("beep 1s x3000000000", 1, 9),
+            (too_many_repeats.as_str(), 1, 18),
+            (too_many_steps.as_str(), 11, 11),
+        ] {
+            match parse(input) {
+                Err(PiShockError::InvalidPatternSyntax { line, column, .. }) => {
+                    assert_eq!((line, column), (expected_line, expected_column));
+                }
+                other => panic!("Expected syntax error, got {other:?}"),
+            }
+        }
+
+        assert_eq!(
+            parse(&format!("beep 1s x{MAX_REPEAT}"))
+                .unwrap()
+                .get_steps()
+                .len(),
+            MAX_REPEAT
+        );
+    }
+}
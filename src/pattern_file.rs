@@ -0,0 +1,410 @@
+use crate::errors::PiShockError;
+use crate::interpolation::{CurveOptions, Easing};
+use crate::pattern::{Pattern, PatternOp};
+use crate::PiShocker;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// The newest pattern file version this crate can read and the one it writes
+pub const PATTERN_FILE_VERSION: u32 = 1;
+
+/// Descriptive information stored alongside the steps of a [`PatternFile`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PatternMetadata {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The highest intensity the author intended the pattern for, parsing fails if a step exceeds it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_intensity: Option<u32>,
+    /// The longest single command the author intended the pattern for, parsing fails if a step exceeds it
+    #[serde(
+        default,
+        rename = "max_duration_ms",
+        with = "optional_duration_ms",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_duration: Option<Duration>,
+}
+
+/// A shareable, versioned [`Pattern`] with metadata that can be stored as JSON or, with the `toml` feature, TOML.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use pishock_rs::pattern::{Pattern, PatternStep};
+/// # use pishock_rs::pattern_file::{PatternFile, PatternMetadata};
+/// let pattern_file = PatternFile::new(
+///     PatternMetadata {
+///         name: "Wake up".to_string(),
+///         ..Default::default()
+///     },
+///     Pattern::new().with_step(PatternStep::beep(Duration::from_secs(1))),
+/// );
+///
+/// let json = pattern_file.to_json().unwrap();
+/// assert_eq!(PatternFile::from_json(&json).unwrap(), pattern_file);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternFile {
+    version: u32,
+    metadata: PatternMetadata,
+    #[serde(rename = "steps")]
+    pattern: Pattern,
+}
+
+impl PatternFile {
+    #[must_use]
+    pub fn new(metadata: PatternMetadata, pattern: Pattern) -> Self {
+        Self {
+            version: PATTERN_FILE_VERSION,
+            metadata,
+            pattern,
+        }
+    }
+
+    #[must_use]
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    #[must_use]
+    pub fn get_metadata(&self) -> &PatternMetadata {
+        &self.metadata
+    }
+
+    #[must_use]
+    pub fn get_pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    /// Parses a pattern file from JSON
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if the JSON is malformed, the version is unsupported
+    /// or a step exceeds the limits declared in the metadata.
+    pub fn from_json(json: &str) -> Result<Self, PiShockError> {
+        serde_json::from_str::<PatternFile>(json)
+            .map_err(|error| PiShockError::InvalidPatternFile(error.to_string()))?
+            .verify_version()?
+            .verify_limits()
+    }
+
+    /// Parses a pattern file from TOML
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if the TOML is malformed, the version is unsupported
+    /// or a step exceeds the limits declared in the metadata.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, PiShockError> {
+        toml::from_str::<PatternFile>(toml)
+            .map_err(|error| PiShockError::InvalidPatternFile(error.to_string()))?
+            .verify_version()?
+            .verify_limits()
+    }
+
+    /// Serializes the pattern file as pretty-printed JSON
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if serialization fails.
+    pub fn to_json(&self) -> Result<String, PiShockError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|error| PiShockError::InvalidPatternFile(error.to_string()))
+    }
+
+    /// Serializes the pattern file as TOML
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if serialization fails.
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, PiShockError> {
+        toml::to_string(self).map_err(|error| PiShockError::InvalidPatternFile(error.to_string()))
+    }
+
+    /// Reads a pattern file, the format is picked by the file extension (`.json` or `.toml`)
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if the file can't be read or parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PiShockError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| PiShockError::InvalidPatternFile(error.to_string()))?;
+
+        match PatternFileFormat::from_path(path)? {
+            PatternFileFormat::Json => Self::from_json(&contents),
+            #[cfg(feature = "toml")]
+            PatternFileFormat::Toml => Self::from_toml(&contents),
+        }
+    }
+
+    /// Writes the pattern file, the format is picked by the file extension (`.json` or `.toml`)
+    ///
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if the file can't be serialized or written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PiShockError> {
+        let path = path.as_ref();
+        let contents = match PatternFileFormat::from_path(path)? {
+            PatternFileFormat::Json => self.to_json()?,
+            #[cfg(feature = "toml")]
+            PatternFileFormat::Toml => self.to_toml()?,
+        };
+
+        std::fs::write(path, contents)
+            .map_err(|error| PiShockError::InvalidPatternFile(error.to_string()))
+    }
+
+    fn verify_version(self) -> Result<Self, PiShockError> {
+        if self.version == 0 || self.version > PATTERN_FILE_VERSION {
+            return Err(PiShockError::InvalidPatternFile(format!(
+                "unsupported version {}, only versions up to {} are supported",
+                self.version, PATTERN_FILE_VERSION
+            )));
+        }
+
+        Ok(self)
+    }
+
+    /// Rejects patterns that don't keep to the limits their own metadata declares
+    fn verify_limits(self) -> Result<Self, PiShockError> {
+        for (index, step) in self.pattern.get_steps().iter().enumerate() {
+            let peak = step.get_intensity().max(step.get_from().unwrap_or(0));
+            if let Some(max_intensity) = self.metadata.max_intensity {
+                if step.get_op().has_intensity() && peak > max_intensity {
+                    return Err(PiShockError::InvalidPatternFile(format!(
+                        "step {index} has intensity {peak}, above the declared maximum of {max_intensity}"
+                    )));
+                }
+            }
+
+            // Ramps are sent as many short commands, only constant steps are a single command
+            let single_command = step.get_op() != PatternOp::Pause
+                && (!step.get_op().has_intensity() || step.get_easing() == Easing::Step);
+            if let Some(max_duration) = self.metadata.max_duration {
+                if single_command && step.get_duration() > max_duration {
+                    return Err(PiShockError::InvalidPatternFile(format!(
+                        "step {index} lasts {:?}, longer than the declared maximum of {max_duration:?}",
+                        step.get_duration()
+                    )));
+                }
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+enum PatternFileFormat {
+    Json,
+    #[cfg(feature = "toml")]
+    Toml,
+}
+
+impl PatternFileFormat {
+    fn from_path(path: &Path) -> Result<Self, PiShockError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Ok(PatternFileFormat::Json),
+            #[cfg(feature = "toml")]
+            Some("toml") => Ok(PatternFileFormat::Toml),
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => Err(PiShockError::InvalidPatternFile(
+                "TOML pattern files need the toml feature".to_string(),
+            )),
+            _ => Err(PiShockError::InvalidPatternFile(format!(
+                "unknown file extension of {}, expected .json or .toml",
+                path.display()
+            ))),
+        }
+    }
+}
+
+impl PiShocker {
+    /// Reads a pattern file and checks it against the limits of this shocker.
+    ///
+    /// ```no_run
+    /// # tokio_test::block_on(async {
+    /// use pishock_rs::interpolation::CurveOptions;
+    /// use pishock_rs::PiShockAccount;
+    ///
+    /// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+    /// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+    ///
+    /// let pattern_file = pishocker_instance.load_pattern_file("heartbeat.toml").expect("Pattern doesn't fit this shocker");
+    /// pishocker_instance.play_pattern(pattern_file.get_pattern(), CurveOptions::default()).await.unwrap();
+    /// # })
+    /// ```
+    /// # Errors
+    /// Returns [`PiShockError::InvalidPatternFile`] if the file can't be read, and the usual intensity
+    /// and duration errors if any step exceeds the shocker limits.
+    pub fn load_pattern_file<P: AsRef<Path>>(&self, path: P) -> Result<PatternFile, PiShockError> {
+        let pattern_file = PatternFile::load(path)?;
+
+        self.plan_pattern(pattern_file.get_pattern(), CurveOptions::default())?;
+
+        Ok(pattern_file)
+    }
+}
+
+/// Serializes a [`Duration`] as whole milliseconds, which is easier to write by hand than seconds and nanoseconds
+pub(crate) mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+mod optional_duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::duration_ms::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::Easing;
+    use crate::pattern::{Pattern, PatternStep};
+    use crate::pattern_file::{PatternFile, PatternMetadata};
+    use crate::pishocker::PiShockerMetadata;
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    fn example_pattern_file() -> PatternFile {
+        PatternFile::new(
+            PatternMetadata {
+                name: "Swell".to_string(),
+                author: Some("pishock_rs".to_string()),
+                description: None,
+                max_intensity: Some(60),
+                max_duration: Some(Duration::from_secs(3)),
+            },
+            Pattern::new()
+                .with_step(PatternStep::beep(Duration::from_millis(500)))
+                .with_step(PatternStep::pause(Duration::from_secs(1)))
+                .with_step(
+                    PatternStep::vibrate(60, Duration::from_secs(3)).with_easing(
+                        Easing::CubicBezier {
+                            x1: 0.4,
+                            y1: 0.0,
+                            x2: 0.2,
+                            y2: 1.0,
+                        },
+                    ),
+                )
+                .with_step(PatternStep::shock(30, Duration::from_secs(1))),
+        )
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let pattern_file = example_pattern_file();
+
+        let json = pattern_file.to_json().unwrap();
+        assert_eq!(PatternFile::from_json(&json).unwrap(), pattern_file);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_round_trip() {
+        let pattern_file = example_pattern_file();
+
+        let toml = pattern_file.to_toml().unwrap();
+        assert_eq!(PatternFile::from_toml(&toml).unwrap(), pattern_file);
+    }
+
+    #[test]
+    fn parse_handwritten_json() {
+        let pattern_file = PatternFile::from_json(
+            r#"{
+                "version": 1,
+                "metadata": { "name": "Ramp" },
+                "steps": [
+                    { "op": "vibrate", "intensity": 50, "duration_ms": 2000, "easing": "ease_in" },
+                    { "op": "pause", "duration_ms": 200 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pattern_file.get_pattern(),
+            &Pattern::new()
+                .with_step(
+                    PatternStep::vibrate(50, Duration::from_secs(2)).with_easing(Easing::EaseIn)
+                )
+                .with_step(PatternStep::pause(Duration::from_millis(200)))
+        );
+    }
+
+    #[test]
+    fn reject_newer_version() {
+        assert!(matches!(
+            PatternFile::from_json(
+                r#"{ "version": 2, "metadata": { "name": "Future" }, "steps": [] }"#
+            ),
+            Err(PiShockError::InvalidPatternFile(_))
+        ));
+    }
+
+    #[test]
+    fn reject_steps_above_declared_limits() {
+        let with_steps = |steps: &str| {
+            PatternFile::from_json(&format!(
+                r#"{{ "version": 1, "metadata": {{ "name": "Capped", "max_intensity": 40, "max_duration_ms": 2000 }}, "steps": [{steps}] }}"#
+            ))
+        };
+
+        assert!(with_steps(r#"{ "op": "shock", "intensity": 40, "duration_ms": 2000 }"#).is_ok());
+        assert!(matches!(
+            with_steps(r#"{ "op": "shock", "intensity": 41, "duration_ms": 1000 }"#),
+            Err(PiShockError::InvalidPatternFile(_))
+        ));
+        assert!(matches!(
+            with_steps(r#"{ "op": "beep", "duration_ms": 2500 }"#),
+            Err(PiShockError::InvalidPatternFile(_))
+        ));
+    }
+
+    #[test]
+    fn load_validates_shocker_limits() {
+        let path =
+            std::env::temp_dir().join(format!("pishock_rs_pattern_{}.json", std::process::id()));
+        example_pattern_file().save(&path).unwrap();
+
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.metadata = Some(PiShockerMetadata {
+            max_intensity: 40,
+            max_duration: 15,
+            online: true,
+            ..Default::default()
+        });
+
+        let result = pishocker_instance.load_pattern_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(PiShockError::InvalidIntensity(40))));
+    }
+}
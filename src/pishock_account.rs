@@ -1,4 +1,5 @@
-use log::debug;
+use crate::emergency::EmergencyStop;
+use crate::safety::SafetyPolicy;
 use crate::{errors, PiShocker};
 
 /// A struct representing PiShock account credentials.
@@ -10,6 +11,8 @@ pub struct PiShockAccount {
     app_name: String,
     api_username: String,
     api_key: String,
+    safety_policy: Option<SafetyPolicy>,
+    pub(crate) emergency_stop: EmergencyStop,
 }
 
 impl PiShockAccount {
@@ -19,9 +22,23 @@ impl PiShockAccount {
             app_name: api_name.into(),
             api_username: api_username.into(),
             api_key: api_key.into(),
+            safety_policy: None,
+            emergency_stop: EmergencyStop::new(),
         }
     }
 
+    /// Sets a safety policy that all shockers of this account are created with
+    #[must_use]
+    pub fn with_safety_policy(mut self, safety_policy: SafetyPolicy) -> Self {
+        self.safety_policy = Some(safety_policy);
+        self
+    }
+
+    #[must_use]
+    pub fn get_safety_policy(&self) -> Option<SafetyPolicy> {
+        self.safety_policy
+    }
+
     /// Returns a [`PiShocker`] instance for the specified share code
     ///
     /// ```
@@ -58,12 +75,14 @@ impl PiShockAccount {
         &self,
         share_code: S,
     ) -> Result<PiShocker, errors::PiShockError> {
-        let pishock_instance = PiShocker::new(
+        let mut pishock_instance = PiShocker::new(
             share_code.into(),
             self.api_key.clone(),
             self.api_username.clone(),
             self.app_name.clone(),
         );
+        pishock_instance.safety_policy = self.safety_policy;
+        pishock_instance.emergency_stop = self.emergency_stop.clone();
 
         Ok(pishock_instance)
     }
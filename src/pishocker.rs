@@ -1,5 +1,11 @@
 use crate::api_endpoints::PiShockOpCode;
+use crate::budget::{ExposureBudget, ExposureLedger};
+use crate::consent::ConsentGate;
+use crate::emergency::EmergencyStop;
 use crate::errors::PiShockError;
+use crate::escalation::{EscalationLimiter, PreviousShock};
+use crate::safety::SafetyPolicy;
+use crate::warning::{WarningPolicy, WarningRequirement};
 use crate::{errors, PUBLIC_PISHOCK_API_BASE};
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
@@ -23,6 +29,14 @@ pub struct PiShocker {
     pub(crate) metadata: Option<PiShockerMetadata>,
     pub(crate) cooldown: Option<Duration>,
     pub(crate) last_shock: Arc<Mutex<Option<Instant>>>,
+    pub(crate) safety_policy: Option<SafetyPolicy>,
+    pub(crate) exposure_budgets: Vec<ExposureBudget>,
+    pub(crate) exposure: Arc<Mutex<ExposureLedger>>,
+    pub(crate) emergency_stop: EmergencyStop,
+    pub(crate) consent_gate: Option<ConsentGate>,
+    pub(crate) warning_policy: Option<WarningPolicy>,
+    pub(crate) escalation_limiter: Option<EscalationLimiter>,
+    pub(crate) last_escalation: Arc<Mutex<Option<(PreviousShock, Instant)>>>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -57,6 +71,14 @@ impl PiShocker {
             metadata: None,
             cooldown: None,
             last_shock: Arc::new(Mutex::new(None)),
+            safety_policy: None,
+            exposure_budgets: Vec::new(),
+            exposure: Arc::new(Mutex::new(ExposureLedger::default())),
+            emergency_stop: EmergencyStop::new(),
+            consent_gate: None,
+            warning_policy: None,
+            escalation_limiter: None,
+            last_escalation: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -140,12 +162,21 @@ impl PiShocker {
     /// </p>
     ///
     /// Refer to documentation of `shock_with_warning` for more information.
+    /// If a [`WarningPolicy`] is set, the shock is still preceded by the warning it requires.
     pub async fn shock(&self, intensity: u32, duration: Duration) -> Result<(), PiShockError> {
         info!(
             "Shocking user with intensity {} and duration {} seconds",
             intensity,
             duration.as_secs()
         );
+
+        if let Some(warning_policy) = self
+            .warning_policy
+            .filter(|warning_policy| warning_policy.is_required(intensity))
+        {
+            return self.warned_shock(warning_policy, intensity, duration).await;
+        }
+
         self.action_api_request(PiShockOpCode::Shock, intensity, duration)
             .await?;
 
@@ -155,6 +186,8 @@ impl PiShocker {
     /// Shocks the user with a short soft warning vibration beforehand.
     /// This is the recommended way to shock someone.
     ///
+    /// The warning of the shocker's [`WarningPolicy`] is used if one is set, regardless of its intensity threshold.
+    ///
     /// ```no_run
     /// # tokio_test::block_on(async {
     /// use std::time::Duration;
@@ -178,14 +211,13 @@ impl PiShocker {
         intensity: u32,
         duration: Duration,
     ) -> Result<(), PiShockError> {
-        debug!("Sending warning vibration");
-        self.vibrate(20, Duration::from_secs(1)).await?;
-
-        tokio::time::sleep(Duration::from_millis(200)).await; // The firmware requires some delay between commands
-        debug!("Sending shock");
-        self.shock(intensity, duration).await?;
+        debug!("Sending shock with warning vibration");
+        let warning_policy = self
+            .warning_policy
+            .unwrap_or_default()
+            .with_requirement(WarningRequirement::EveryShock);
 
-        Ok(())
+        self.warned_shock(warning_policy, intensity, duration).await
     }
 
     /// Set a cooldown for the shocker
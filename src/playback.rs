@@ -0,0 +1,684 @@
+use crate::emergency::EmergencyStop;
+use crate::errors::PiShockError;
+use crate::interpolation::{CurveOptions, CurvePlan, PlannedStep, PlannedSteps};
+use crate::pattern::{Pattern, PatternOp};
+use crate::PiShocker;
+use log::debug;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Progress reported by a [`PlaybackHandle`]
+#[derive(Debug, Clone)]
+pub enum PlaybackEvent {
+    /// A step of the plan is about to be sent, `elapsed` is the time into the plan
+    Step {
+        index: usize,
+        elapsed: Duration,
+        op: PatternOp,
+        intensity: u32,
+    },
+    /// Playback was paused before the step with the given index
+    Paused { index: usize },
+    /// Playback continues with the step with the given index
+    Resumed { index: usize },
+    /// Playback jumped to the step with the given index
+    Skipped { index: usize },
+    /// The request for a step completed, successful or not
+    Sent(StepTiming),
+    /// Sending the step with the given index failed, playback ends after this event
+    Error { index: usize, error: PiShockError },
+    /// Playback was stopped before the end of the plan
+    Stopped { index: usize },
+    /// All steps were played
+    Finished,
+}
+
+/// When a step was sent compared to when the plan scheduled it
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StepTiming {
+    index: usize,
+    scheduled: Duration,
+    sent: Duration,
+    compensation: Duration,
+    request_time: Duration,
+}
+
+impl StepTiming {
+    /// Returns the index of the step in [`CurvePlan::get_steps`]
+    #[must_use]
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the offset the plan scheduled the step at
+    #[must_use]
+    pub fn get_scheduled(&self) -> Duration {
+        self.scheduled
+    }
+
+    /// Returns the time into the plan the request was started, time spent paused is not counted
+    #[must_use]
+    pub fn get_sent(&self) -> Duration {
+        self.sent
+    }
+
+    /// Returns how much earlier than scheduled the request was started to make up for the estimated latency
+    #[must_use]
+    pub fn get_compensation(&self) -> Duration {
+        self.compensation
+    }
+
+    /// Returns how long the request took
+    #[must_use]
+    pub fn get_request_time(&self) -> Duration {
+        self.request_time
+    }
+
+    /// Returns how late the command is estimated to have arrived in milliseconds, negative if it was early
+    #[must_use]
+    pub fn get_error_ms(&self) -> i64 {
+        let arrival = (self.sent + self.compensation).as_millis() as i64;
+
+        arrival - self.scheduled.as_millis() as i64
+    }
+}
+
+/// The timing of all steps that were sent during a playback
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimingReport {
+    steps: Vec<StepTiming>,
+}
+
+impl TimingReport {
+    #[must_use]
+    pub fn get_steps(&self) -> &[StepTiming] {
+        &self.steps
+    }
+
+    /// Returns the largest timing error in milliseconds, early or late, 0 if nothing was sent
+    #[must_use]
+    pub fn get_max_error_ms(&self) -> i64 {
+        self.steps
+            .iter()
+            .map(|step| step.get_error_ms().abs())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PlaybackControl {
+    Pause,
+    Resume,
+    Stop,
+    SkipTo(usize),
+}
+
+/// What interrupted a wait between two steps
+enum WaitOutcome {
+    Elapsed,
+    Stop,
+    SkipTo(usize),
+    EmergencyStop,
+}
+
+/// Controls a pattern started with [`PiShocker::play`].
+///
+/// Dropping the handle stops the playback after the command that is currently being sent.
+#[derive(Debug)]
+pub struct PlaybackHandle {
+    plan: CurvePlan,
+    control: mpsc::UnboundedSender<PlaybackControl>,
+    events: mpsc::UnboundedReceiver<PlaybackEvent>,
+    task: JoinHandle<Result<TimingReport, PiShockError>>,
+}
+
+impl PlaybackHandle {
+    /// Returns the plan that is being played, useful to draw a progress bar
+    #[must_use]
+    pub fn get_plan(&self) -> &CurvePlan {
+        &self.plan
+    }
+
+    /// Pauses before the next step, the command that is currently being sent is not interrupted
+    pub fn pause(&self) {
+        // Sending only fails if playback is already over
+        let _ = self.control.send(PlaybackControl::Pause);
+    }
+
+    pub fn resume(&self) {
+        let _ = self.control.send(PlaybackControl::Resume);
+    }
+
+    /// Stops before the next step, the command that is currently being sent is not interrupted
+    pub fn stop(&self) {
+        let _ = self.control.send(PlaybackControl::Stop);
+    }
+
+    /// Jumps to the step with the given index of [`CurvePlan::get_steps`], skipping past the end finishes playback
+    pub fn skip_to(&self, step: usize) {
+        let _ = self.control.send(PlaybackControl::SkipTo(step));
+    }
+
+    /// Waits for the next progress event, returns `None` once playback is over and all events were read
+    pub async fn next_event(&mut self) -> Option<PlaybackEvent> {
+        self.events.recv().await
+    }
+
+    /// Waits until playback is over and returns the timing of all steps that were sent
+    ///
+    /// # Errors
+    /// Returns the error of the first command that failed.
+    pub async fn wait(self) -> Result<TimingReport, PiShockError> {
+        self.task
+            .await
+            .unwrap_or_else(|error| Err(PiShockError::UnknownError(error.to_string())))
+    }
+}
+
+impl PiShocker {
+    /// Starts playing a [`Pattern`] in the background and returns a handle to control it.
+    ///
+    /// ```no_run
+    /// # tokio_test::block_on(async {
+    /// use std::time::Duration;
+    /// use pishock_rs::interpolation::CurveOptions;
+    /// use pishock_rs::PiShockAccount;
+    /// use pishock_rs::playback::PlaybackEvent;
+    ///
+    /// let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+    /// let pishocker_instance = pishock_account.get_shocker("sharecode".to_string()).await.unwrap();
+    ///
+    /// let pattern = "vib 10 ramp-> 60 over 10s; shock 20 1s".parse().unwrap();
+    /// let mut playback = pishocker_instance.play(pattern, CurveOptions::default()).unwrap();
+    /// let total = playback.get_plan().get_total_duration();
+    ///
+    /// while let Some(event) = playback.next_event().await {
+    ///     if let PlaybackEvent::Step { elapsed, intensity, .. } = event {
+    ///         println!("{:.0}% - intensity {intensity}", elapsed.as_secs_f32() / total.as_secs_f32() * 100.0);
+    ///     }
+    /// }
+    ///
+    /// playback.wait().await.expect("Playback failed");
+    /// # })
+    /// ```
+    /// # Errors
+    /// Fails right away if the pattern can't be planned, see [`PiShocker::plan_pattern`].
+    pub fn play(
+        &self,
+        pattern: Pattern,
+        options: CurveOptions,
+    ) -> Result<PlaybackHandle, PiShockError> {
+        let plan = self.plan_pattern(&pattern, options)?;
+
+        let (control_sender, control_receiver) = mpsc::unbounded_channel();
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+
+        let shocker = self.clone();
+        let task_plan = plan.clone();
+        let task = tokio::spawn(async move {
+            let seek = |index: usize| {
+                task_plan
+                    .get_steps()
+                    .get(index..)
+                    .unwrap_or_default()
+                    .iter()
+                    .copied()
+            };
+
+            shocker
+                .run_steps(
+                    seek,
+                    task_plan.get_options().get_step_gap(),
+                    control_receiver,
+                    Some(event_sender),
+                )
+                .await
+        });
+
+        Ok(PlaybackHandle {
+            plan,
+            control: control_sender,
+            events: event_receiver,
+            task,
+        })
+    }
+
+    /// Sends all planned steps as they are computed, waiting for the gaps and pauses in between
+    pub(crate) async fn play_steps(&self, steps: PlannedSteps<'_>) -> Result<(), PiShockError> {
+        // Nothing ever controls this playback, keep the sender alive so it isn't treated as stopped
+        let (_control_sender, control_receiver) = mpsc::unbounded_channel();
+        let step_gap = steps.get_options().get_step_gap();
+
+        self.run_steps(
+            |index| steps.clone().skip(index),
+            step_gap,
+            control_receiver,
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sends every step at its offset from a common start instant, so the time spent on requests
+    /// doesn't add up over the course of the plan.
+    ///
+    /// `seek` returns the steps starting at an index, it is called once at the start and on every skip.
+    async fn run_steps<I, S>(
+        &self,
+        seek: S,
+        step_gap: Duration,
+        mut control: mpsc::UnboundedReceiver<PlaybackControl>,
+        events: Option<mpsc::UnboundedSender<PlaybackEvent>>,
+    ) -> Result<TimingReport, PiShockError>
+    where
+        I: Iterator<Item = PlannedStep>,
+        S: Fn(usize) -> I,
+    {
+        let emit = |event: PlaybackEvent| {
+            if let Some(events) = &events {
+                // Nobody listening for events is fine
+                let _ = events.send(event);
+            }
+        };
+
+        // Check consent for the whole timeline first, so a pattern isn't cut off after its first commands
+        let missing_consent = self.consent_gate.as_ref().and_then(|consent_gate| {
+            let grant = consent_gate.get_grant();
+            seek(0).enumerate().find_map(|(index, step)| {
+                let violation = consent_gate.check_grant(
+                    grant.as_ref(),
+                    step.get_op().op_code()?,
+                    step.get_intensity(),
+                )?;
+                Some((index, PiShockError::ConsentRequired(violation)))
+            })
+        });
+        if let Some((index, error)) = missing_consent {
+            emit(PlaybackEvent::Error {
+                index,
+                error: error.clone(),
+            });
+            return Err(error);
+        }
+
+        let mut steps = seek(0).peekable();
+        let mut index = 0;
+        // The end of the last step taken from the timeline, where playback finishes
+        let mut timeline_end = Duration::ZERO;
+        // The instant the plan timeline started, moved forward while paused
+        let mut start = Instant::now();
+        // Commands are sent this much early so they arrive on time
+        let mut compensation = Duration::ZERO;
+        // The last command sent has to be over for the step gap before the next one, even after a skip
+        let mut next_allowed: Option<Instant> = None;
+        let mut report = TimingReport::default();
+
+        loop {
+            // Pauses are covered by the wait before the next command
+            while let Some(pause) = steps.next_if(|step| step.get_op().op_code().is_none()) {
+                timeline_end = pause.get_offset() + pause.get_duration();
+                index += 1;
+            }
+
+            // Trailing pauses still count, so patterns can be chained back to back
+            let deadline = match steps.peek() {
+                Some(step) => {
+                    let target = start + step.get_offset();
+                    target.checked_sub(compensation).unwrap_or(target)
+                }
+                None => start + timeline_end,
+            };
+
+            let outcome = wait_or_control(
+                deadline,
+                index,
+                &mut start,
+                &mut control,
+                &self.emergency_stop,
+                &emit,
+            )
+            .await;
+
+            match outcome {
+                WaitOutcome::Elapsed => {}
+                WaitOutcome::EmergencyStop => {
+                    emit(PlaybackEvent::Error {
+                        index,
+                        error: PiShockError::EmergencyStopped,
+                    });
+                    return Err(PiShockError::EmergencyStopped);
+                }
+                WaitOutcome::Stop => {
+                    emit(PlaybackEvent::Stopped { index });
+                    return Ok(report);
+                }
+                WaitOutcome::SkipTo(skip_index) => {
+                    index = skip_index;
+                    steps = seek(index).peekable();
+                    // Continue the timeline from the new step as soon as the shocker is ready for it
+                    let offset = steps.peek().map_or(timeline_end, PlannedStep::get_offset);
+                    let resume = next_allowed.map_or(Instant::now(), |next_allowed| {
+                        next_allowed.max(Instant::now())
+                    });
+                    start = resume.checked_sub(offset).unwrap_or(resume);
+                    emit(PlaybackEvent::Skipped { index });
+                    continue;
+                }
+            }
+
+            let Some(step) = steps.next() else {
+                break;
+            };
+            timeline_end = step.get_offset() + step.get_duration();
+            // Pause steps were skipped above
+            let op_code = step.get_op().op_code().unwrap();
+
+            emit(PlaybackEvent::Step {
+                index,
+                elapsed: step.get_offset(),
+                op: step.get_op(),
+                intensity: step.get_intensity(),
+            });
+
+            debug!(
+                "Sending {:?} at intensity {} for duration {:#?}",
+                step.get_op(),
+                step.get_intensity(),
+                step.get_duration()
+            );
+            let sent = Instant::now();
+            let result = self
+                .action_api_request(op_code, step.get_intensity(), step.get_duration())
+                .await;
+            let request_time = sent.elapsed();
+
+            let timing = StepTiming {
+                index,
+                scheduled: step.get_offset(),
+                sent: sent.saturating_duration_since(start),
+                compensation,
+                request_time,
+            };
+            debug!("Step {index} timing error: {}ms", timing.get_error_ms());
+            report.steps.push(timing);
+            emit(PlaybackEvent::Sent(timing));
+
+            if let Err(error) = result {
+                emit(PlaybackEvent::Error {
+                    index,
+                    error: error.clone(),
+                });
+                return Err(error);
+            }
+
+            next_allowed = Some(sent + step.get_duration() + step_gap);
+            compensation = next_compensation(
+                compensation,
+                report.steps.len() == 1,
+                step.get_duration(),
+                request_time,
+            )
+            .min(step_gap);
+            index += 1;
+        }
+
+        emit(PlaybackEvent::Finished);
+
+        Ok(report)
+    }
+}
+
+/// Estimates the one-way latency from the time a request took and smooths it over the previous estimates.
+///
+/// The API may only respond once the command is done, in that case only the time beyond the command duration is latency.
+fn next_compensation(
+    previous: Duration,
+    first: bool,
+    duration: Duration,
+    request_time: Duration,
+) -> Duration {
+    let round_trip = if request_time >= duration {
+        request_time - duration
+    } else {
+        request_time
+    };
+    let one_way = round_trip / 2;
+
+    if first {
+        one_way
+    } else {
+        (previous * 3 + one_way) / 4
+    }
+}
+
+/// Waits until `deadline` while handling control messages and the emergency stop, time spent paused moves `start` and the deadline back
+async fn wait_or_control<F: Fn(PlaybackEvent)>(
+    mut deadline: Instant,
+    index: usize,
+    start: &mut Instant,
+    control: &mut mpsc::UnboundedReceiver<PlaybackControl>,
+    emergency_stop: &EmergencyStop,
+    emit: &F,
+) -> WaitOutcome {
+    loop {
+        // Check for control messages first, so they are handled even if there is nothing to wait for
+        let message = tokio::select! {
+            biased;
+            () = emergency_stop.tripped() => return WaitOutcome::EmergencyStop,
+            message = control.recv() => message,
+            () = tokio::time::sleep_until(deadline) => return WaitOutcome::Elapsed,
+        };
+
+        match message {
+            Some(PlaybackControl::Pause) => {
+                let paused_at = Instant::now();
+                emit(PlaybackEvent::Paused { index });
+
+                loop {
+                    let message = tokio::select! {
+                        biased;
+                        () = emergency_stop.tripped() => return WaitOutcome::EmergencyStop,
+                        message = control.recv() => message,
+                    };
+
+                    match message {
+                        Some(PlaybackControl::Resume) => break,
+                        Some(PlaybackControl::Pause) => {}
+                        Some(PlaybackControl::SkipTo(skip_index)) => {
+                            return WaitOutcome::SkipTo(skip_index)
+                        }
+                        Some(PlaybackControl::Stop) | None => return WaitOutcome::Stop,
+                    }
+                }
+
+                emit(PlaybackEvent::Resumed { index });
+                let paused = paused_at.elapsed();
+                *start += paused;
+                deadline += paused;
+            }
+            Some(PlaybackControl::Resume) => {}
+            Some(PlaybackControl::SkipTo(skip_index)) => return WaitOutcome::SkipTo(skip_index),
+            // A dropped handle stops playback
+            Some(PlaybackControl::Stop) | None => return WaitOutcome::Stop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::PatternOp;
+    use crate::playback::PlaybackEvent;
+    use crate::PiShockAccount;
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+    use test_log::test;
+    use tokio::time::Instant;
+
+    async fn events_of(playback: &mut crate::playback::PlaybackHandle) -> Vec<PlaybackEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = playback.next_event().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test(tokio::test)]
+    async fn stop_before_first_step() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        let mut playback = pishocker_instance
+            .play("vib 20 1s x3".parse().unwrap(), CurveOptions::default())
+            .unwrap();
+        playback.stop();
+
+        let events = events_of(&mut playback).await;
+        assert!(matches!(
+            events.as_slice(),
+            [PlaybackEvent::Stopped { index: 0 }]
+        ));
+        playback.wait().await.unwrap();
+
+        mock.assert_hits(0);
+    }
+
+    #[test(tokio::test)]
+    async fn skip_to_last_step() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/").json_body(json!({
+                "Op": 0,
+                "Intensity": 30,
+                "Duration": 1,
+                "Code": "sharecode",
+                "Apikey": "apikey",
+                "Name": "pishock_rs",
+                "Username": "username"
+            }));
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        let mut playback = pishocker_instance
+            .play(
+                "vib 20 1s; wait 1s; shock 30 1s".parse().unwrap(),
+                CurveOptions::default(),
+            )
+            .unwrap();
+        playback.pause();
+        playback.skip_to(2);
+
+        let events = events_of(&mut playback).await;
+        assert!(matches!(
+            events.as_slice(),
+            [
+                PlaybackEvent::Paused { index: 0 },
+                PlaybackEvent::Skipped { index: 2 },
+                PlaybackEvent::Step {
+                    index: 2,
+                    op: PatternOp::Shock,
+                    intensity: 30,
+                    ..
+                },
+                PlaybackEvent::Sent(timing),
+                PlaybackEvent::Finished,
+            ] if timing.get_index() == 2
+        ));
+        playback.wait().await.unwrap();
+
+        mock.assert_hits(1);
+    }
+
+    #[test(tokio::test)]
+    async fn skip_keeps_the_step_gap() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        let mut playback = pishocker_instance
+            .play(
+                "vib 20 1s; wait 5s; vib 30 1s".parse().unwrap(),
+                CurveOptions::default(),
+            )
+            .unwrap();
+        while !matches!(playback.next_event().await, Some(PlaybackEvent::Sent(_))) {}
+        let first_sent = Instant::now();
+        playback.skip_to(2);
+
+        while !matches!(
+            playback.next_event().await,
+            Some(PlaybackEvent::Step { index: 2, .. })
+        ) {}
+        // The first command lasts 1s and is followed by the 100ms step gap
+        assert!(first_sent.elapsed() >= Duration::from_millis(1000));
+
+        playback.wait().await.unwrap();
+        mock.assert_hits(2);
+    }
+
+    #[test(tokio::test)]
+    async fn request_latency_does_not_accumulate() {
+        let mockserver = MockServer::start();
+        // The request takes longer than the command, like an API that responds once the command is done
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200)
+                .body("Operation Succeeded.")
+                .delay(Duration::from_millis(290));
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        let playback = pishocker_instance
+            .play("vib 20 200ms x6".parse().unwrap(), CurveOptions::default())
+            .unwrap();
+        let report = playback.wait().await.unwrap();
+
+        // Steps are planned 300ms apart, waiting 100ms after every 290ms request would make the
+        // last step 450ms late. The bound leaves plenty of room for a slow machine.
+        assert_eq!(report.get_steps().len(), 6);
+        assert!(
+            report.get_max_error_ms() <= 200,
+            "timing error too large: {report:?}"
+        );
+
+        mock.assert_hits(6);
+    }
+}
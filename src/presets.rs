@@ -0,0 +1,376 @@
+//! Ready-made patterns that are generated from a few parameters.
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pishock_rs::presets::{Preset, PresetOptions};
+//!
+//! let options = PresetOptions::default()
+//!     .with_tempo(80)
+//!     .with_peak_intensity(40)
+//!     .with_length(Duration::from_secs(30));
+//!
+//! let heartbeat = Preset::Heartbeat.generate(&options);
+//! let same = pishock_rs::presets::by_name("heartbeat", &options).unwrap();
+//! assert_eq!(heartbeat, same);
+//! ```
+
+use crate::errors::PiShockError;
+use crate::interpolation::Easing;
+use crate::morse::encode_with_unit;
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::validation::MIN_DURATION;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Parameters shared by all presets
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PresetOptions {
+    op: PatternOp,
+    tempo: u32,
+    peak_intensity: u32,
+    length: Duration,
+}
+
+impl Default for PresetOptions {
+    fn default() -> Self {
+        Self {
+            op: PatternOp::Vibrate,
+            tempo: 60,
+            peak_intensity: 50,
+            length: Duration::from_secs(10),
+        }
+    }
+}
+
+impl PresetOptions {
+    /// Sets the operation the preset is played with (default [`PatternOp::Vibrate`])
+    #[must_use]
+    pub fn with_op(mut self, op: PatternOp) -> Self {
+        self.op = op;
+        self
+    }
+
+    /// Sets the speed in beats per minute (default 60), commands never get shorter than 100ms
+    #[must_use]
+    pub fn with_tempo(mut self, tempo: u32) -> Self {
+        self.tempo = tempo;
+        self
+    }
+
+    /// Sets the highest intensity the preset reaches (default 50)
+    #[must_use]
+    pub fn with_peak_intensity(mut self, peak_intensity: u32) -> Self {
+        self.peak_intensity = peak_intensity;
+        self
+    }
+
+    /// Sets how long the preset should last (default 10 seconds), rounded to whole cycles
+    #[must_use]
+    pub fn with_length(mut self, length: Duration) -> Self {
+        self.length = length;
+        self
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> PatternOp {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_tempo(&self) -> u32 {
+        self.tempo
+    }
+
+    #[must_use]
+    pub fn get_peak_intensity(&self) -> u32 {
+        self.peak_intensity
+    }
+
+    #[must_use]
+    pub fn get_length(&self) -> Duration {
+        self.length
+    }
+
+    fn beat(&self) -> Duration {
+        Duration::from_secs(60) / self.tempo.max(1)
+    }
+
+    fn peak(&self) -> u32 {
+        self.peak_intensity.clamp(1, 100)
+    }
+
+    /// A fraction of the peak intensity, at least 1
+    fn fraction_of_peak(&self, fraction: f32) -> u32 {
+        ((self.peak() as f32 * fraction).round() as u32).max(1)
+    }
+
+    /// A single command that is long enough for the API
+    fn command(&self, intensity: u32, duration: Duration) -> PatternStep {
+        let duration = duration.max(MIN_DURATION);
+
+        match self.op {
+            PatternOp::Beep => PatternStep::beep(duration),
+            op => PatternStep::new(op, intensity, duration),
+        }
+    }
+
+    /// Repeats a cycle as often as it fits into the length, at least once
+    fn fill_length(&self, cycle: &Pattern) -> Pattern {
+        let cycle_duration = cycle.get_total_duration().as_secs_f32();
+        let count = if cycle_duration > 0.0 {
+            (self.length.as_secs_f32() / cycle_duration).round() as usize
+        } else {
+            1
+        };
+
+        cycle.repeat(count.max(1))
+    }
+
+    /// The number of beats that fit into the length, at least one
+    fn beats(&self) -> u32 {
+        ((self.length.as_secs_f32() / self.beat().as_secs_f32()).round() as u32).max(1)
+    }
+}
+
+/// The built-in presets
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Preset {
+    /// A strong and a weaker beat followed by a rest, once per beat
+    Heartbeat,
+    /// Smoothly swells to the peak and back down over four beats
+    Wave,
+    /// Short pulses that climb evenly to the peak over the whole length
+    Ladder,
+    /// Even pulses at the peak intensity, half a beat on and half a beat off
+    PulseTrain,
+    /// Ramps that break off and start over, each one getting closer to the peak
+    Tease,
+    /// The morse code for SOS, one morse unit is half a beat but at least 100ms
+    Sos,
+}
+
+impl Preset {
+    /// All presets, e.g. to list them in a UI
+    pub const ALL: [Preset; 6] = [
+        Preset::Heartbeat,
+        Preset::Wave,
+        Preset::Ladder,
+        Preset::PulseTrain,
+        Preset::Tease,
+        Preset::Sos,
+    ];
+
+    /// Returns the name used by [`by_name`] and the `Display` and `FromStr` implementations
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Heartbeat => "heartbeat",
+            Preset::Wave => "wave",
+            Preset::Ladder => "ladder",
+            Preset::PulseTrain => "pulse_train",
+            Preset::Tease => "tease",
+            Preset::Sos => "sos",
+        }
+    }
+
+    /// Generates the preset with the given options
+    #[must_use]
+    pub fn generate(&self, options: &PresetOptions) -> Pattern {
+        match self {
+            Preset::Heartbeat => heartbeat(options),
+            Preset::Wave => wave(options),
+            Preset::Ladder => ladder(options),
+            Preset::PulseTrain => pulse_train(options),
+            Preset::Tease => tease(options),
+            Preset::Sos => sos(options),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = PiShockError;
+
+    /// Looks up a preset by name, ignoring case and accepting `-` in place of `_`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalized = name.trim().to_lowercase().replace('-', "_");
+
+        Preset::ALL
+            .into_iter()
+            .find(|preset| preset.name() == normalized)
+            .ok_or_else(|| PiShockError::UnknownPreset(name.to_string()))
+    }
+}
+
+/// Generates a preset by its name, see [`Preset::name`]
+///
+/// # Errors
+/// Returns [`PiShockError::UnknownPreset`] if there is no preset with that name.
+pub fn by_name(name: &str, options: &PresetOptions) -> Result<Pattern, PiShockError> {
+    Ok(name.parse::<Preset>()?.generate(options))
+}
+
+fn heartbeat(options: &PresetOptions) -> Pattern {
+    let beat = options.beat();
+    let thump = (beat * 3 / 20).max(MIN_DURATION);
+    let between = (beat / 10).max(MIN_DURATION);
+
+    let cycle = Pattern::new()
+        .with_step(options.command(options.peak(), thump))
+        .with_step(PatternStep::pause(between))
+        .with_step(options.command(options.fraction_of_peak(0.7), thump))
+        .with_step(PatternStep::pause(
+            beat.saturating_sub(thump * 2 + between).max(MIN_DURATION),
+        ));
+
+    options.fill_length(&cycle)
+}
+
+fn wave(options: &PresetOptions) -> Pattern {
+    let low = options.fraction_of_peak(0.2);
+    let half = options.beat() * 2;
+
+    let cycle = Pattern::new()
+        .with_step(
+            options
+                .command(options.peak(), half)
+                .with_from(low)
+                .with_easing(Easing::Sine),
+        )
+        .with_step(
+            options
+                .command(low, half)
+                .with_from(options.peak())
+                .with_easing(Easing::Sine),
+        );
+
+    options.fill_length(&cycle)
+}
+
+fn ladder(options: &PresetOptions) -> Pattern {
+    let rungs = options.beats();
+    let half = options.beat() / 2;
+
+    (1..=rungs)
+        .flat_map(|rung| {
+            [
+                options.command(options.fraction_of_peak(rung as f32 / rungs as f32), half),
+                PatternStep::pause(half.max(MIN_DURATION)),
+            ]
+        })
+        .collect()
+}
+
+fn pulse_train(options: &PresetOptions) -> Pattern {
+    let half = options.beat() / 2;
+
+    let cycle = Pattern::new()
+        .with_step(options.command(options.peak(), half))
+        .with_step(PatternStep::pause(half.max(MIN_DURATION)));
+
+    options.fill_length(&cycle)
+}
+
+fn tease(options: &PresetOptions) -> Pattern {
+    let beat = options.beat();
+    // Every ramp takes two beats and is followed by a one beat rest
+    let ramps = ((options.length.as_secs_f32() / (beat * 3).as_secs_f32()).round() as u32).max(1);
+
+    (1..=ramps)
+        .flat_map(|ramp| {
+            [
+                options
+                    .command(
+                        options.fraction_of_peak(ramp as f32 / ramps as f32),
+                        beat * 2,
+                    )
+                    .with_from(1)
+                    .with_easing(Easing::EaseIn),
+                PatternStep::pause(beat.max(MIN_DURATION)),
+            ]
+        })
+        .collect()
+}
+
+fn sos(options: &PresetOptions) -> Pattern {
+    let unit = (options.beat() / 2).max(MIN_DURATION);
+
+    let cycle = encode_with_unit("SOS", unit, |duration| {
+        options.command(options.peak(), duration)
+    })
+    // SOS only contains supported characters
+    .unwrap()
+    // The gap between two words
+    .with_step(PatternStep::pause(unit * 7));
+
+    options.fill_length(&cycle)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::PatternOp;
+    use crate::presets::{by_name, Preset, PresetOptions};
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    #[test]
+    fn all_presets_can_be_planned() {
+        let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+
+        for tempo in [30, 60, 240] {
+            let options = PresetOptions::default()
+                .with_tempo(tempo)
+                .with_peak_intensity(80);
+
+            for preset in Preset::ALL {
+                let pattern = preset.generate(&options);
+                assert!(!pattern.is_empty(), "{preset} is empty");
+
+                let plan = pishocker_instance
+                    .plan_pattern(&pattern, CurveOptions::default())
+                    .unwrap_or_else(|error| panic!("{preset} at {tempo} bpm: {error}"));
+                assert!(plan.get_peak_intensity() <= 80, "{preset} is too strong");
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        assert_eq!("Pulse-Train".parse::<Preset>().unwrap(), Preset::PulseTrain);
+        assert!(matches!(
+            by_name("metronome", &PresetOptions::default()),
+            Err(PiShockError::UnknownPreset(name)) if name == "metronome"
+        ));
+
+        for preset in Preset::ALL {
+            assert_eq!(preset.to_string().parse::<Preset>().unwrap(), preset);
+        }
+    }
+
+    #[test]
+    fn ladder_climbs_to_peak() {
+        let pattern = Preset::Ladder.generate(
+            &PresetOptions::default()
+                .with_op(PatternOp::Shock)
+                .with_peak_intensity(40)
+                .with_length(Duration::from_secs(4)),
+        );
+
+        let intensities: Vec<u32> = pattern
+            .get_steps()
+            .iter()
+            .filter(|step| step.get_op() == PatternOp::Shock)
+            .map(|step| step.get_intensity())
+            .collect();
+        assert_eq!(intensities, vec![10, 20, 30, 40]);
+        assert_eq!(pattern.get_total_duration(), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,293 @@
+//! Seeded generators for "unpredictable" patterns that are still bounded and reproducible.
+//!
+//! The same seed and the same sequence of calls always produce the same patterns, so a session can
+//! be replayed exactly by logging the seed. The random number generator is part of this crate, its
+//! output won't change with dependency updates.
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use pishock_rs::pattern::{Pattern, PatternOp};
+//! # use pishock_rs::random::{Envelope, RandomPatterns};
+//! let envelope = Envelope::new(10..=40, Duration::from_millis(500)..=Duration::from_secs(2));
+//!
+//! let first = RandomPatterns::new(1234).random_steps(PatternOp::Vibrate, 8, &envelope);
+//! let replay = RandomPatterns::new(1234).random_steps(PatternOp::Vibrate, 8, &envelope);
+//! assert_eq!(first, replay);
+//! ```
+
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::PiShocker;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// The longest single command the API accepts
+const API_MAX_DURATION: Duration = Duration::from_secs(15);
+
+/// The bounds random steps are picked from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    intensity: RangeInclusive<u32>,
+    duration: RangeInclusive<Duration>,
+}
+
+impl Envelope {
+    #[must_use]
+    pub fn new(intensity: RangeInclusive<u32>, duration: RangeInclusive<Duration>) -> Self {
+        Self {
+            intensity,
+            duration,
+        }
+    }
+}
+
+/// SplitMix64, small and fast with good enough statistics for picking intensities
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a float in 0.0..1.0
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a value in the inclusive range, the bounds may be in any order
+    fn range_u64(&mut self, low: u64, high: u64) -> u64 {
+        let (low, high) = (low.min(high), low.max(high));
+        let span = high - low;
+        if span == u64::MAX {
+            return self.next_u64();
+        }
+
+        low + self.next_u64() % (span + 1)
+    }
+
+    fn range_i64(&mut self, low: i64, high: i64) -> i64 {
+        let (low, high) = (low.min(high), low.max(high));
+        low + self.range_u64(0, high.abs_diff(low)) as i64
+    }
+
+    fn range_duration(&mut self, range: &RangeInclusive<Duration>) -> Duration {
+        Duration::from_millis(self.range_u64(
+            range.start().as_millis() as u64,
+            range.end().as_millis() as u64,
+        ))
+    }
+}
+
+/// Generates random patterns from an explicit seed.
+///
+/// Every pattern is clamped to the limits of the shocker passed to [`RandomPatterns::within_limits_of`],
+/// or to the API limits (intensity 100, 15 seconds) if none was given.
+#[derive(Debug, Clone)]
+pub struct RandomPatterns {
+    seed: u64,
+    rng: SplitMix64,
+    max_intensity: u32,
+    max_duration: Duration,
+}
+
+impl RandomPatterns {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: SplitMix64(seed),
+            max_intensity: 100,
+            max_duration: API_MAX_DURATION,
+        }
+    }
+
+    /// Keeps all generated patterns within [`PiShocker::get_max_intensity`] and [`PiShocker::get_max_duration`]
+    #[must_use]
+    pub fn within_limits_of(mut self, shocker: &PiShocker) -> Self {
+        if let Some(max_intensity) = shocker.get_max_intensity() {
+            self.max_intensity = max_intensity.clamp(1, 100) as u32;
+        }
+
+        if let Some(max_duration) = shocker.get_max_duration() {
+            self.max_duration = max_duration.min(API_MAX_DURATION);
+        }
+
+        self
+    }
+
+    /// Returns the seed this generator was created with
+    #[must_use]
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Randomly moves each intensity up or down by at most `intensity`, and stretches or shrinks each
+    /// duration by at most the `timing` fraction (0.2 = ±20%).
+    pub fn jitter(&mut self, pattern: &Pattern, intensity: u32, timing: f32) -> Pattern {
+        let intensity = i64::from(intensity);
+        let timing = f64::from(timing.clamp(0.0, 1.0));
+
+        let jittered: Pattern = pattern
+            .get_steps()
+            .iter()
+            .map(|step| {
+                let mut step = step.clone();
+
+                if step.get_op().has_intensity() {
+                    let offset = self.rng.range_i64(-intensity, intensity);
+                    let jittered_intensity =
+                        (i64::from(step.get_intensity()) + offset).clamp(1, 100);
+                    step = step.with_intensity(jittered_intensity as u32);
+                }
+
+                // Durations too long to represent saturate, they are clamped to the limits anyway
+                let factor = 1.0 + (self.rng.next_f64() * 2.0 - 1.0) * timing;
+                let duration =
+                    Duration::try_from_secs_f64(step.get_duration().as_secs_f64() * factor)
+                        .unwrap_or(Duration::MAX);
+                step.with_duration(duration)
+            })
+            .collect();
+
+        self.fit(&jittered)
+    }
+
+    /// Inserts a random pause after each step with the given probability (0.0 to 1.0)
+    pub fn insert_pauses(
+        &mut self,
+        pattern: &Pattern,
+        probability: f32,
+        pause: RangeInclusive<Duration>,
+    ) -> Pattern {
+        let mut with_pauses = Pattern::new();
+
+        for step in pattern.get_steps() {
+            with_pauses.push(step.clone());
+
+            if self.rng.next_f64() < f64::from(probability) {
+                with_pauses.push(PatternStep::pause(self.rng.range_duration(&pause)));
+            }
+        }
+
+        self.fit(&with_pauses)
+    }
+
+    /// Picks `count` steps of the given op with random intensities and durations within the envelope
+    pub fn random_steps(&mut self, op: PatternOp, count: usize, envelope: &Envelope) -> Pattern {
+        let pattern: Pattern = (0..count)
+            .map(|_| {
+                let intensity = if op.has_intensity() {
+                    self.rng.range_u64(
+                        u64::from(*envelope.intensity.start()),
+                        u64::from(*envelope.intensity.end()),
+                    ) as u32
+                } else {
+                    0
+                };
+
+                PatternStep::new(op, intensity, self.rng.range_duration(&envelope.duration))
+            })
+            .collect();
+
+        self.fit(&pattern)
+    }
+
+    fn fit(&self, pattern: &Pattern) -> Pattern {
+        pattern.clamp_to_limits(self.max_intensity, self.max_duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::{Pattern, PatternOp, PatternStep};
+    use crate::pishocker::PiShockerMetadata;
+    use crate::random::{Envelope, RandomPatterns};
+    use crate::PiShocker;
+    use std::time::Duration;
+
+    fn limited_shocker() -> PiShocker {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.metadata = Some(PiShockerMetadata {
+            max_intensity: 30,
+            max_duration: 2,
+            online: true,
+            ..Default::default()
+        });
+        pishocker_instance
+    }
+
+    fn generate(seed: u64, shocker: &PiShocker) -> Pattern {
+        let envelope = Envelope::new(
+            1..=100,
+            Duration::from_millis(100)..=Duration::from_secs(10),
+        );
+        let mut random = RandomPatterns::new(seed).within_limits_of(shocker);
+
+        let steps = random.random_steps(PatternOp::Shock, 10, &envelope);
+        let jittered = random.jitter(&steps, 20, 0.5);
+        random.insert_pauses(
+            &jittered,
+            0.5,
+            Duration::from_millis(100)..=Duration::from_secs(1),
+        )
+    }
+
+    #[test]
+    fn same_seed_same_pattern() {
+        let shocker = limited_shocker();
+
+        assert_eq!(generate(42, &shocker), generate(42, &shocker));
+        assert_ne!(generate(42, &shocker), generate(43, &shocker));
+    }
+
+    #[test]
+    fn stays_within_limits() {
+        let shocker = limited_shocker();
+
+        for seed in 0..100 {
+            let pattern = generate(seed, &shocker);
+
+            for step in pattern
+                .get_steps()
+                .iter()
+                .filter(|step| step.get_op() != PatternOp::Pause)
+            {
+                assert!(
+                    (1..=30).contains(&step.get_intensity()),
+                    "seed {seed}: {step:?}"
+                );
+                assert!(
+                    step.get_duration() <= Duration::from_secs(2),
+                    "seed {seed}: {step:?}"
+                );
+            }
+
+            assert!(shocker
+                .plan_pattern(&pattern, CurveOptions::default())
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn jitter_huge_durations() {
+        let pattern = Pattern::new()
+            .with_step(PatternStep::vibrate(20, Duration::MAX))
+            .with_step(PatternStep::pause(Duration::MAX));
+        let mut random = RandomPatterns::new(7);
+
+        for _ in 0..20 {
+            // Stretching past the longest duration saturates instead of panicking
+            let jittered = random.jitter(&pattern, 0, 1.0);
+            assert_eq!(
+                jittered.get_steps()[0].get_duration(),
+                Duration::from_secs(15)
+            );
+        }
+    }
+}
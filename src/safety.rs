@@ -0,0 +1,273 @@
+//! App-enforced ceilings that apply on top of the limits the wearer set for a shocker.
+//!
+//! A [`SafetyPolicy`] is checked for every command right before it is sent, no matter whether it comes
+//! from [`PiShocker::shock`], a curve or a pattern. Patterns are checked as a whole before anything is sent.
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pishock_rs::safety::SafetyPolicy;
+//! use pishock_rs::{PiShockAccount, PiShockOpCode};
+//!
+//! let public_mode = SafetyPolicy::default()
+//!     .with_max_intensity(PiShockOpCode::Shock, 60)
+//!     .with_max_duration(PiShockOpCode::Shock, Duration::from_secs(3));
+//!
+//! let pishock_account =
+//!     PiShockAccount::new("pishock_rs", "username", "apikey").with_safety_policy(public_mode);
+//! ```
+
+use crate::validation::Command;
+use crate::{PiShockOpCode, PiShocker};
+use std::fmt;
+use std::time::Duration;
+
+/// The ceilings of a single operation
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+struct OperationCaps {
+    forbidden: bool,
+    max_intensity: Option<u32>,
+    max_duration: Option<Duration>,
+}
+
+/// Hard caps for intensities and durations, per operation.
+///
+/// The default policy doesn't restrict anything. Caps above the shocker limits have no effect, the
+/// shocker limits still apply.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SafetyPolicy {
+    shock: OperationCaps,
+    vibrate: OperationCaps,
+    beep: OperationCaps,
+}
+
+impl SafetyPolicy {
+    /// Caps the intensity of an operation, beeps don't have an intensity
+    #[must_use]
+    pub fn with_max_intensity(mut self, op: PiShockOpCode, max_intensity: u32) -> Self {
+        self.caps_mut(op).max_intensity = Some(max_intensity);
+        self
+    }
+
+    /// Caps the duration of a single command of an operation
+    #[must_use]
+    pub fn with_max_duration(mut self, op: PiShockOpCode, max_duration: Duration) -> Self {
+        self.caps_mut(op).max_duration = Some(max_duration);
+        self
+    }
+
+    /// Forbids an operation entirely, e.g. to only allow vibrations
+    #[must_use]
+    pub fn with_forbidden(mut self, op: PiShockOpCode) -> Self {
+        self.caps_mut(op).forbidden = true;
+        self
+    }
+
+    #[must_use]
+    pub fn get_max_intensity(&self, op: PiShockOpCode) -> Option<u32> {
+        self.caps(op).max_intensity
+    }
+
+    #[must_use]
+    pub fn get_max_duration(&self, op: PiShockOpCode) -> Option<Duration> {
+        self.caps(op).max_duration
+    }
+
+    #[must_use]
+    pub fn is_forbidden(&self, op: PiShockOpCode) -> bool {
+        self.caps(op).forbidden
+    }
+
+    /// Returns the first cap the command exceeds, if any
+    #[must_use]
+    pub fn check(&self, command: &Command) -> Option<SafetyViolation> {
+        let op = command.get_op();
+        let caps = self.caps(op);
+
+        if caps.forbidden {
+            return Some(SafetyViolation::OperationForbidden(op));
+        }
+
+        if let Some(max) = caps.max_intensity {
+            if op != PiShockOpCode::Beep && command.get_intensity() > max {
+                return Some(SafetyViolation::IntensityAboveCap {
+                    op,
+                    intensity: command.get_intensity(),
+                    max,
+                });
+            }
+        }
+
+        if let Some(max) = caps.max_duration {
+            if command.get_duration() > max {
+                return Some(SafetyViolation::DurationAboveCap {
+                    op,
+                    duration: command.get_duration(),
+                    max,
+                });
+            }
+        }
+
+        None
+    }
+
+    fn caps(&self, op: PiShockOpCode) -> &OperationCaps {
+        match op {
+            PiShockOpCode::Shock => &self.shock,
+            PiShockOpCode::Vibrate => &self.vibrate,
+            PiShockOpCode::Beep => &self.beep,
+        }
+    }
+
+    fn caps_mut(&mut self, op: PiShockOpCode) -> &mut OperationCaps {
+        match op {
+            PiShockOpCode::Shock => &mut self.shock,
+            PiShockOpCode::Vibrate => &mut self.vibrate,
+            PiShockOpCode::Beep => &mut self.beep,
+        }
+    }
+}
+
+/// The reason a command was rejected by a [`SafetyPolicy`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SafetyViolation {
+    OperationForbidden(PiShockOpCode),
+    IntensityAboveCap {
+        op: PiShockOpCode,
+        intensity: u32,
+        max: u32,
+    },
+    DurationAboveCap {
+        op: PiShockOpCode,
+        duration: Duration,
+        max: Duration,
+    },
+}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafetyViolation::OperationForbidden(op) => write!(f, "{op:?} is not allowed"),
+            SafetyViolation::IntensityAboveCap { op, intensity, max } => {
+                write!(f, "{op:?} intensity {intensity} is above the cap of {max}")
+            }
+            SafetyViolation::DurationAboveCap { op, duration, max } => {
+                write!(
+                    f,
+                    "{op:?} duration {duration:?} is above the cap of {max:?}"
+                )
+            }
+        }
+    }
+}
+
+impl PiShocker {
+    /// Sets the safety policy every command of this shocker is checked against
+    ///
+    /// ```
+    /// # use pishock_rs::PiShocker;
+    /// # use pishock_rs::PiShockOpCode;
+    /// # use pishock_rs::safety::SafetyPolicy;
+    /// let mut pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+    /// pishocker_instance.set_safety_policy(SafetyPolicy::default().with_forbidden(PiShockOpCode::Shock));
+    /// ```
+    pub fn set_safety_policy(&mut self, safety_policy: SafetyPolicy) {
+        self.safety_policy = Some(safety_policy);
+    }
+
+    /// Returns the safety policy of the shocker, if any
+    #[must_use]
+    pub fn get_safety_policy(&self) -> Option<SafetyPolicy> {
+        self.safety_policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PiShockError;
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::Pattern;
+    use crate::safety::{SafetyPolicy, SafetyViolation};
+    use crate::validation::Command;
+    use crate::{PiShockAccount, PiShockOpCode};
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use std::time::Duration;
+    use test_log::test;
+
+    fn public_mode() -> SafetyPolicy {
+        SafetyPolicy::default()
+            .with_max_intensity(PiShockOpCode::Shock, 60)
+            .with_max_duration(PiShockOpCode::Shock, Duration::from_secs(3))
+    }
+
+    #[test(tokio::test)]
+    async fn every_path_is_capped() {
+        let mockserver = MockServer::start();
+        let mock = mockserver.mock(|when, then| {
+            when.method(POST).path("/apioperate/");
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey")
+            .with_safety_policy(public_mode());
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+
+        assert!(matches!(
+            pishocker_instance.shock(61, Duration::from_secs(1)).await,
+            Err(PiShockError::SafetyPolicyViolation(
+                SafetyViolation::IntensityAboveCap { max: 60, .. }
+            ))
+        ));
+        assert!(matches!(
+            pishocker_instance.mini_shock(80).await,
+            Err(PiShockError::SafetyPolicyViolation(_))
+        ));
+        // The warning vibration must not be sent if the shock is rejected
+        assert!(matches!(
+            pishocker_instance
+                .shock_with_warning(20, Duration::from_secs(4))
+                .await,
+            Err(PiShockError::SafetyPolicyViolation(
+                SafetyViolation::DurationAboveCap { .. }
+            ))
+        ));
+        assert!(matches!(
+            pishocker_instance
+                .shock_curve(
+                    "shock 10 ramp-> 90 over 2s".parse::<Pattern>().unwrap(),
+                    CurveOptions::default()
+                )
+                .await,
+            Err(PiShockError::SafetyPolicyViolation(_))
+        ));
+        mock.assert_hits(0);
+
+        // Other operations and commands within the caps still work
+        pishocker_instance
+            .vibrate(100, Duration::from_secs(5))
+            .await
+            .unwrap();
+        pishocker_instance
+            .shock(60, Duration::from_secs(3))
+            .await
+            .unwrap();
+        mock.assert_hits(2);
+    }
+
+    #[test]
+    fn forbidden_operations() {
+        let policy = public_mode().with_forbidden(PiShockOpCode::Vibrate);
+        let vibrate = Command::vibrate(1, Duration::from_secs(1));
+
+        assert!(policy.is_forbidden(PiShockOpCode::Vibrate));
+        assert_eq!(
+            policy.check(&vibrate),
+            Some(SafetyViolation::OperationForbidden(PiShockOpCode::Vibrate))
+        );
+        assert_eq!(policy.check(&Command::beep(Duration::from_secs(15))), None);
+    }
+}
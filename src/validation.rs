@@ -0,0 +1,286 @@
+use crate::api_endpoints::PiShockOpCode;
+use crate::consent::ConsentViolation;
+use crate::errors::PiShockError;
+use crate::safety::SafetyViolation;
+use crate::PiShocker;
+use std::time::Duration;
+
+/// The shortest duration the PiShock API accepts
+pub(crate) const MIN_DURATION: Duration = Duration::from_millis(100);
+
+/// A single action that can be checked with [`PiShocker::validate`] before sending it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Command {
+    op: PiShockOpCode,
+    intensity: u32,
+    duration: Duration,
+}
+
+impl Command {
+    #[must_use]
+    pub fn new(op: PiShockOpCode, intensity: u32, duration: Duration) -> Self {
+        Self {
+            op,
+            intensity,
+            duration,
+        }
+    }
+
+    /// A beep with the specified duration, the intensity is ignored by the API
+    #[must_use]
+    pub fn beep(duration: Duration) -> Self {
+        Self::new(PiShockOpCode::Beep, 0, duration)
+    }
+
+    #[must_use]
+    pub fn vibrate(intensity: u32, duration: Duration) -> Self {
+        Self::new(PiShockOpCode::Vibrate, intensity, duration)
+    }
+
+    #[must_use]
+    pub fn shock(intensity: u32, duration: Duration) -> Self {
+        Self::new(PiShockOpCode::Shock, intensity, duration)
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> PiShockOpCode {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// A single reason why a [`Command`] would be rejected
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Violation {
+    /// The [`crate::emergency::EmergencyStop`] latch is tripped
+    EmergencyStopped,
+    ShockerOffline,
+    ShockerPaused,
+    /// The duration exceeds the maximum duration of the shocker
+    DurationTooLong {
+        max: Duration,
+    },
+    /// The intensity exceeds the maximum intensity of the shocker
+    IntensityTooHigh {
+        max: u32,
+    },
+    /// The shocker cooldown is not over yet, contains the remaining time
+    CooldownActive(Duration),
+    /// The API does not accept intensities below 1, beeps have no intensity and are never rejected for it
+    IntensityTooLow,
+    /// The API does not accept durations below 100 milliseconds
+    DurationTooShort,
+    /// The command exceeds a cap of the shocker's [`crate::safety::SafetyPolicy`]
+    SafetyPolicy(SafetyViolation),
+    /// The wearer didn't consent to the command, see [`crate::consent::ConsentGate`]
+    Consent(ConsentViolation),
+}
+
+impl PiShocker {
+    /// Checks a [`Command`] against the shocker state and limits without sending it.
+    ///
+    /// Unlike sending a command, this has no side effects (the cooldown timer is left untouched),
+    /// so it can be used to grey out invalid choices in a UI. All problems are reported at once,
+    /// an empty list means the command would currently be accepted.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use pishock_rs::PiShocker;
+    /// # use pishock_rs::validation::{Command, Violation};
+    /// let pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+    ///
+    /// let violations = pishocker_instance.validate(&Command::shock(0, Duration::from_millis(50)));
+    /// assert_eq!(violations, vec![Violation::IntensityTooLow, Violation::DurationTooShort]);
+    /// ```
+    #[must_use]
+    pub fn validate(&self, command: &Command) -> Vec<Violation> {
+        self.collect_violations(command, true)
+    }
+
+    /// Like [`PiShocker::validate`] without the consent gate, for plans that check consent once before playing
+    pub(crate) fn validate_without_consent(&self, command: &Command) -> Vec<Violation> {
+        self.collect_violations(command, false)
+    }
+
+    fn collect_violations(&self, command: &Command, check_consent: bool) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if self.emergency_stop.is_tripped() {
+            violations.push(Violation::EmergencyStopped);
+        }
+
+        if self.get_shocker_online() == Some(false) {
+            violations.push(Violation::ShockerOffline);
+        }
+
+        if self.get_shocker_paused() == Some(true) {
+            violations.push(Violation::ShockerPaused);
+        }
+
+        if self
+            .max_duration_error_triggered(command.duration)
+            .is_some()
+        {
+            violations.push(Violation::DurationTooLong {
+                max: self.get_max_duration().unwrap(),
+            });
+        }
+
+        // Beeps don't have an intensity. They used to fail the intensity check because they are sent
+        // with intensity 0, which made every beep fail with an InvalidIntensity error.
+        let has_intensity = command.op != PiShockOpCode::Beep;
+
+        if has_intensity
+            && self
+                .max_intensity_error_triggered(command.intensity)
+                .is_some()
+        {
+            violations.push(Violation::IntensityTooHigh {
+                max: self.get_max_intensity().unwrap() as u32,
+            });
+        }
+
+        if let Some(remaining) = self.remaining_cooldown() {
+            violations.push(Violation::CooldownActive(remaining));
+        }
+
+        if has_intensity && command.intensity < 1 {
+            violations.push(Violation::IntensityTooLow);
+        }
+
+        if command.duration < MIN_DURATION {
+            violations.push(Violation::DurationTooShort);
+        }
+
+        if let Some(violation) = self
+            .safety_policy
+            .and_then(|safety_policy| safety_policy.check(command))
+        {
+            violations.push(Violation::SafetyPolicy(violation));
+        }
+
+        if let Some(violation) = self
+            .consent_gate
+            .as_ref()
+            .filter(|_| check_consent)
+            .and_then(|consent_gate| consent_gate.check(command.op, command.intensity))
+        {
+            violations.push(Violation::Consent(violation));
+        }
+
+        violations
+    }
+
+    /// Returns the time left until the cooldown is over, without resetting it
+    pub(crate) fn remaining_cooldown(&self) -> Option<Duration> {
+        let last_shock = self.last_shock.lock().unwrap();
+        let cooldown = self.get_shocker_cooldown()?;
+        let elapsed = last_shock.as_ref()?.elapsed();
+
+        if elapsed < cooldown {
+            Some(cooldown - elapsed)
+        } else {
+            None
+        }
+    }
+
+    /// Converts a [`Violation`] to the error the API request would have returned
+    pub(crate) fn violation_to_error(&self, violation: Violation) -> PiShockError {
+        match violation {
+            Violation::EmergencyStopped => PiShockError::EmergencyStopped,
+            Violation::ShockerOffline => PiShockError::ShockerOffline,
+            Violation::ShockerPaused => PiShockError::ShockerPaused,
+            Violation::DurationTooLong { max } => {
+                PiShockError::InvalidDuration(max.as_secs() as u32)
+            }
+            Violation::IntensityTooHigh { max } => PiShockError::InvalidIntensity(max),
+            Violation::CooldownActive(remaining) => PiShockError::CooldownExceeded(remaining),
+            Violation::IntensityTooLow => PiShockError::InvalidIntensity(
+                self.get_max_intensity()
+                    .map_or(100, |max_intensity| max_intensity as u32),
+            ),
+            Violation::DurationTooShort => PiShockError::InvalidDuration(
+                self.get_max_duration()
+                    .map_or(15, |max_duration| max_duration.as_secs() as u32),
+            ),
+            Violation::SafetyPolicy(violation) => PiShockError::SafetyPolicyViolation(violation),
+            Violation::Consent(violation) => PiShockError::ConsentRequired(violation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pishocker::PiShockerMetadata;
+    use crate::validation::{Command, Violation};
+    use crate::PiShocker;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn shocker_with_metadata() -> PiShocker {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.metadata = Some(PiShockerMetadata {
+            client_id: 1612,
+            id: 2955,
+            name: "test 1".to_string(),
+            paused: true,
+            max_intensity: 40,
+            max_duration: 5,
+            online: true,
+        });
+        pishocker_instance
+    }
+
+    #[test]
+    fn validate_reports_all_violations() {
+        let pishocker_instance = shocker_with_metadata();
+
+        assert_eq!(
+            pishocker_instance.validate(&Command::shock(60, Duration::from_secs(10))),
+            vec![
+                Violation::ShockerPaused,
+                Violation::DurationTooLong {
+                    max: Duration::from_secs(5)
+                },
+                Violation::IntensityTooHigh { max: 40 },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_ignores_beep_intensity() {
+        let mut pishocker_instance = shocker_with_metadata();
+        pishocker_instance.metadata.as_mut().unwrap().paused = false;
+
+        assert!(pishocker_instance
+            .validate(&Command::beep(Duration::from_secs(1)))
+            .is_empty());
+    }
+
+    #[test]
+    fn validate_does_not_reset_cooldown() {
+        let mut pishocker_instance = shocker_with_metadata();
+        pishocker_instance.metadata.as_mut().unwrap().paused = false;
+        pishocker_instance.set_shocker_cooldown(Duration::from_secs(60));
+
+        let command = Command::vibrate(20, Duration::from_secs(1));
+        assert!(pishocker_instance.validate(&command).is_empty());
+        assert!(pishocker_instance.last_shock.lock().unwrap().is_none());
+
+        *pishocker_instance.last_shock.lock().unwrap() = Some(Instant::now());
+        assert!(matches!(
+            pishocker_instance.validate(&command).as_slice(),
+            [Violation::CooldownActive(_)]
+        ));
+    }
+}
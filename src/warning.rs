@@ -0,0 +1,323 @@
+//! A mandatory warning before shocks, so the wearer always knows one is coming.
+//!
+//! Once a [`WarningPolicy`] is set on a shocker, every shock is preceded by the warning: plain
+//! [`PiShocker::shock`] calls as well as patterns and curves, where each run of consecutive shock
+//! steps gets a warning in front of it.
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pishock_rs::warning::{WarningOp, WarningPolicy, WarningRequirement};
+//! use pishock_rs::PiShocker;
+//!
+//! let mut pishocker_instance = PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+//! pishocker_instance.set_warning_policy(
+//!     WarningPolicy::default()
+//!         .with_op(WarningOp::Beep)
+//!         .with_duration(Duration::from_millis(500))
+//!         .with_lead_time(Duration::from_secs(2))
+//!         .with_requirement(WarningRequirement::Above(30)),
+//! );
+//! ```
+
+use crate::errors::PiShockError;
+use crate::interpolation::CurveOptions;
+use crate::pattern::{Pattern, PatternOp, PatternStep};
+use crate::PiShocker;
+use log::debug;
+use std::borrow::Cow;
+use std::time::Duration;
+
+/// The operation a warning is given with
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum WarningOp {
+    Beep,
+    #[default]
+    Vibrate,
+}
+
+/// Which shocks need a warning
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum WarningRequirement {
+    #[default]
+    EveryShock,
+    /// Only shocks with an intensity above the given one
+    Above(u32),
+}
+
+/// How and when shocks are announced, the default is what [`PiShocker::shock_with_warning`] has always sent:
+/// a 1 second vibration at intensity 20, 200ms before every shock.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct WarningPolicy {
+    op: WarningOp,
+    intensity: u32,
+    duration: Duration,
+    lead_time: Duration,
+    requirement: WarningRequirement,
+}
+
+impl Default for WarningPolicy {
+    fn default() -> Self {
+        Self {
+            op: WarningOp::Vibrate,
+            intensity: 20,
+            duration: Duration::from_secs(1),
+            // The firmware requires some delay between commands
+            lead_time: Duration::from_millis(200),
+            requirement: WarningRequirement::EveryShock,
+        }
+    }
+}
+
+impl WarningPolicy {
+    /// Sets the operation of the warning (default [`WarningOp::Vibrate`])
+    #[must_use]
+    pub fn with_op(mut self, op: WarningOp) -> Self {
+        self.op = op;
+        self
+    }
+
+    /// Sets the intensity of a vibration warning (default 20)
+    #[must_use]
+    pub fn with_intensity(mut self, intensity: u32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Sets how long the warning lasts (default 1 second)
+    #[must_use]
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Sets the time between the end of the warning and the shock (default 200ms)
+    #[must_use]
+    pub fn with_lead_time(mut self, lead_time: Duration) -> Self {
+        self.lead_time = lead_time;
+        self
+    }
+
+    /// Sets which shocks need a warning (default [`WarningRequirement::EveryShock`])
+    #[must_use]
+    pub fn with_requirement(mut self, requirement: WarningRequirement) -> Self {
+        self.requirement = requirement;
+        self
+    }
+
+    #[must_use]
+    pub fn get_op(&self) -> WarningOp {
+        self.op
+    }
+
+    #[must_use]
+    pub fn get_intensity(&self) -> u32 {
+        self.intensity
+    }
+
+    #[must_use]
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    #[must_use]
+    pub fn get_lead_time(&self) -> Duration {
+        self.lead_time
+    }
+
+    #[must_use]
+    pub fn get_requirement(&self) -> WarningRequirement {
+        self.requirement
+    }
+
+    /// Returns whether a shock with this intensity needs a warning
+    #[must_use]
+    pub fn is_required(&self, intensity: u32) -> bool {
+        match self.requirement {
+            WarningRequirement::EveryShock => true,
+            WarningRequirement::Above(threshold) => intensity > threshold,
+        }
+    }
+
+    /// Inserts a warning before every run of consecutive shock steps that needs one.
+    ///
+    /// Runs that are already preceded by this exact warning are left alone, so applying a policy twice
+    /// doesn't warn twice.
+    ///
+    /// ```
+    /// # use pishock_rs::pattern::Pattern;
+    /// # use pishock_rs::warning::WarningPolicy;
+    /// let pattern: Pattern = "shock 30 1s; wait 2s; shock 30 1s x2".parse().unwrap();
+    /// let warned = WarningPolicy::default().apply(&pattern);
+    ///
+    /// assert_eq!(warned.to_string(), "vib 20 1s; wait 200ms; shock 30 1s; wait 2s; vib 20 1s; wait 200ms; shock 30 1s x2");
+    /// assert_eq!(WarningPolicy::default().apply(&warned), warned);
+    /// ```
+    #[must_use]
+    pub fn apply(&self, pattern: &Pattern) -> Pattern {
+        self.warn(Cow::Borrowed(pattern)).into_owned()
+    }
+
+    /// The steps inserted before a shock
+    fn warning_steps(&self) -> Vec<PatternStep> {
+        let mut steps = vec![match self.op {
+            WarningOp::Beep => PatternStep::beep(self.duration),
+            WarningOp::Vibrate => PatternStep::vibrate(self.intensity, self.duration),
+        }];
+
+        if !self.lead_time.is_zero() {
+            steps.push(PatternStep::pause(self.lead_time));
+        }
+
+        steps
+    }
+
+    /// Like [`WarningPolicy::apply`], but only copies the pattern if a warning is missing
+    pub(crate) fn warn<'a>(&self, pattern: Cow<'a, Pattern>) -> Cow<'a, Pattern> {
+        let warning = self.warning_steps();
+        let steps = pattern.get_steps();
+        let mut missing = Vec::new();
+
+        for (index, step) in steps.iter().enumerate() {
+            let starts_run = step.get_op() == PatternOp::Shock
+                && (index == 0 || steps[index - 1].get_op() != PatternOp::Shock);
+            if !starts_run {
+                continue;
+            }
+
+            let peak = steps[index..]
+                .iter()
+                .take_while(|step| step.get_op() == PatternOp::Shock)
+                .map(|step| step.get_intensity().max(step.get_from().unwrap_or(0)))
+                .max()
+                .unwrap_or(0);
+            let warned = steps[..index].ends_with(&warning);
+
+            if self.is_required(peak) && !warned {
+                missing.push(index);
+            }
+        }
+
+        if missing.is_empty() {
+            return pattern;
+        }
+
+        debug!("Adding {} shock warnings", missing.len());
+        let mut warned = Pattern::new();
+        for (index, step) in steps.iter().enumerate() {
+            if missing.contains(&index) {
+                for warning_step in &warning {
+                    warned.push(warning_step.clone());
+                }
+            }
+            warned.push(step.clone());
+        }
+
+        Cow::Owned(warned)
+    }
+}
+
+impl PiShocker {
+    /// Sets the warning policy every shock of this shocker has to follow
+    pub fn set_warning_policy(&mut self, warning_policy: WarningPolicy) {
+        self.warning_policy = Some(warning_policy);
+    }
+
+    /// Returns the warning policy of the shocker, if any
+    #[must_use]
+    pub fn get_warning_policy(&self) -> Option<WarningPolicy> {
+        self.warning_policy
+    }
+
+    /// Sends a single shock with the warning the policy requires for it
+    pub(crate) async fn warned_shock(
+        &self,
+        warning_policy: WarningPolicy,
+        intensity: u32,
+        duration: Duration,
+    ) -> Result<(), PiShockError> {
+        let pattern = Pattern::new().with_step(PatternStep::shock(intensity, duration));
+
+        self.play_pattern(&warning_policy.apply(&pattern), CurveOptions::default())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::CurveOptions;
+    use crate::pattern::{Pattern, PatternOp};
+    use crate::warning::{WarningOp, WarningPolicy, WarningRequirement};
+    use crate::{PiShockAccount, PiShocker};
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn plain_shocks_are_warned() {
+        let mockserver = MockServer::start();
+        let beep_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 2, "Duration": 500 }).to_string());
+            then.status(200).body("Operation Succeeded.");
+        });
+        let shock_mock = mockserver.mock(|when, then| {
+            when.method(POST)
+                .path("/apioperate/")
+                .json_body_partial(json!({ "Op": 0 }).to_string());
+            then.status(200).body("Operation Succeeded.");
+        });
+
+        let pishock_account = PiShockAccount::new("pishock_rs", "username", "apikey");
+        let mut pishocker_instance = pishock_account
+            .get_shocker_without_verification("sharecode")
+            .await
+            .unwrap();
+        pishocker_instance.set_api_server_url(mockserver.url(""));
+        pishocker_instance.set_warning_policy(
+            WarningPolicy::default()
+                .with_op(WarningOp::Beep)
+                .with_duration(Duration::from_millis(500))
+                .with_requirement(WarningRequirement::Above(30)),
+        );
+
+        pishocker_instance.mini_shock(30).await.unwrap();
+        beep_mock.assert_hits(0);
+
+        pishocker_instance.mini_shock(31).await.unwrap();
+        beep_mock.assert_hits(1);
+        shock_mock.assert_hits(2);
+    }
+
+    #[test]
+    fn curves_are_warned() {
+        let mut pishocker_instance =
+            PiShocker::new("sharecode", "apikey", "username", "pishock_rs");
+        pishocker_instance.set_warning_policy(WarningPolicy::default());
+
+        let pattern: Pattern = "vib 10 1s; shock 10 ramp-> 60 over 1s; wait 2s; shock 30 1s"
+            .parse()
+            .unwrap();
+        let plan = pishocker_instance
+            .plan_pattern(&pattern, CurveOptions::default())
+            .unwrap();
+
+        let ops: Vec<PatternOp> = plan.get_steps().iter().map(|step| step.get_op()).collect();
+        assert_eq!(
+            ops,
+            vec![
+                PatternOp::Vibrate,
+                PatternOp::Vibrate,
+                PatternOp::Pause,
+                PatternOp::Shock,
+                PatternOp::Shock,
+                PatternOp::Pause,
+                PatternOp::Vibrate,
+                PatternOp::Pause,
+                PatternOp::Shock,
+            ]
+        );
+    }
+}